@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use arbitrary_int::{u4, u12};
+
+use crate::error::AsmError;
+use crate::instructions::Inst;
+
+/// Assemble a program written with the same mnemonics produced by the disassembler, to be loaded at `origin`.
+///
+/// Besides instructions, each line can contain:
+/// - a label definition: `name:`
+/// - a constant definition: `name = value`
+/// - data: `db 0xF0, 0x90` or `dw 0x1234`
+/// - a comment, starting with `;`
+///
+/// Values are decimal, hexadecimal (`0x`) or binary (`0b`) numbers, labels and constants,
+/// optionally combined with `+` and `-`. `LD I, LONG value` loads a 16-bit address (XO-CHIP)
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler {
+        symbols: HashMap::new(),
+        line: 0,
+    };
+
+    // First pass: collect the statements and compute the address of every label
+    let mut statements = Vec::new();
+    let mut addr = origin as usize;
+    for (number, text) in source.lines().enumerate() {
+        asm.line = number + 1;
+        let mut text = text.split(';').next().unwrap().trim();
+
+        if let Some((name, value)) = text.split_once('=') {
+            let name = asm.symbol_name(name.trim())?;
+            asm.define(name, Symbol::Const(value.trim().to_string(), asm.line))?;
+            continue;
+        }
+
+        while let Some((name, rest)) = text.split_once(':') {
+            let name = asm.symbol_name(name.trim())?;
+            asm.define(name, Symbol::Label(addr as i64))?;
+            text = rest.trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.split(',').map(str::trim).collect()),
+            None => (text, Vec::new()),
+        };
+        let mnemonic = mnemonic.to_ascii_uppercase();
+
+        addr += match mnemonic.as_str() {
+            "DB" => operands.len(),
+            "DW" => operands.len() * 2,
+            "LD" if operands.len() == 2 && is_long(operands[1]) => 4,
+            _ => 2,
+        };
+        statements.push((asm.line, mnemonic, operands));
+    }
+
+    // Second pass: encode everything now that all the symbols are known
+    let mut out = Vec::new();
+    for (line, mnemonic, operands) in statements {
+        asm.line = line;
+        match mnemonic.as_str() {
+            "DB" => {
+                for operand in operands {
+                    out.push(asm.value(operand, -0x80, 0xFF)? as u8);
+                }
+            }
+            "DW" => {
+                for operand in operands {
+                    out.extend_from_slice(&(asm.value(operand, -0x8000, 0xFFFF)? as u16).to_be_bytes());
+                }
+            }
+            _ => {
+                let operands: Vec<Operand> = operands.iter().map(|operand| Operand::parse(operand)).collect();
+                let (inst, long) = asm.inst(&mnemonic, &operands)?;
+                out.extend_from_slice(&inst.encode().to_be_bytes());
+                if let Some(long) = long {
+                    out.extend_from_slice(&long.to_be_bytes());
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+const MNEMONICS: &[&str] = &[
+    "SYS", "CLS", "RET", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL",
+    "RND", "DRW", "SKP", "SKNP", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SCU", "SAVE", "LOAD", "PLANE", "AUDIO",
+    "PITCH",
+];
+
+enum Symbol {
+    Label(i64),
+    Const(String, usize),
+}
+
+#[derive(Clone, Copy)]
+enum Operand<'a> {
+    V(u4),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+    Long(&'a str),
+    Value(&'a str),
+}
+
+impl<'a> Operand<'a> {
+    fn parse(text: &'a str) -> Operand<'a> {
+        let upper = text.to_ascii_uppercase();
+        match upper.as_str() {
+            "I" => return Operand::I,
+            "[I]" => return Operand::IndirectI,
+            "DT" => return Operand::DT,
+            "ST" => return Operand::ST,
+            "K" => return Operand::K,
+            "F" => return Operand::F,
+            "HF" => return Operand::HF,
+            "B" => return Operand::B,
+            "R" => return Operand::R,
+            _ => (),
+        }
+
+        if is_long(text) {
+            return Operand::Long(text[4..].trim());
+        }
+
+        let mut chars = upper.chars();
+        if let (Some('V'), Some(digit), None) = (chars.next(), chars.next(), chars.next()) {
+            if let Some(reg) = digit.to_digit(16) {
+                return Operand::V(u4::new(reg as u8));
+            }
+        }
+
+        Operand::Value(text)
+    }
+}
+
+fn is_long(operand: &str) -> bool {
+    operand.get(..4).is_some_and(|prefix| prefix.eq_ignore_ascii_case("LONG"))
+        && operand[4..].starts_with(char::is_whitespace)
+}
+
+struct Assembler {
+    symbols: HashMap<String, Symbol>,
+    line: usize,
+}
+
+impl Assembler {
+    fn error<T>(&self, message: String) -> Result<T, AsmError> {
+        Err(AsmError { line: self.line, message })
+    }
+
+    fn symbol_name<'a>(&self, name: &'a str) -> Result<&'a str, AsmError> {
+        let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return self.error(format!("invalid symbol name '{}'", name));
+        }
+        Ok(name)
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), AsmError> {
+        if self.symbols.contains_key(name) {
+            return self.error(format!("'{}' is already defined", name));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    /// Evaluate `expr`, checking it is within `min..=max`
+    fn value(&self, expr: &str, min: i64, max: i64) -> Result<i64, AsmError> {
+        let value = self.eval(expr, 0).or_else(|message| self.error(message))?;
+        if value < min || value > max {
+            return self.error(format!("value {} out of range, must be between {} and {}", expr, min, max));
+        }
+        Ok(value)
+    }
+
+    fn eval(&self, expr: &str, depth: usize) -> Result<i64, String> {
+        if depth > 16 {
+            return Err("constants are defined recursively".to_string());
+        }
+
+        let mut total = 0;
+        let mut sign = 1;
+        let mut rest = expr.trim();
+        if let Some(stripped) = rest.strip_prefix('-') {
+            sign = -1;
+            rest = stripped.trim_start();
+        }
+
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            total += sign * self.term(rest[..end].trim(), depth)?;
+            if end == rest.len() {
+                return Ok(total);
+            }
+            sign = if rest[end..].starts_with('+') { 1 } else { -1 };
+            rest = rest[end + 1..].trim_start();
+        }
+    }
+
+    fn term(&self, term: &str, depth: usize) -> Result<i64, String> {
+        let lower = term.to_ascii_lowercase();
+        let number = if let Some(hex) = lower.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(bin) = lower.strip_prefix("0b") {
+            i64::from_str_radix(bin, 2).ok()
+        } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+            term.parse().ok()
+        } else {
+            None
+        };
+        if let Some(number) = number {
+            return Ok(number);
+        }
+        if term.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(format!("invalid number '{}'", term));
+        }
+
+        match self.symbols.get(term) {
+            Some(Symbol::Label(addr)) => Ok(*addr),
+            Some(Symbol::Const(expr, line)) => {
+                self.eval(expr, depth + 1).map_err(|message| format!("{} (in constant defined at line {})", message, line))
+            }
+            None if term.is_empty() => Err("missing value".to_string()),
+            None => Err(format!("unknown symbol '{}'", term)),
+        }
+    }
+
+    fn byte(&self, expr: &str) -> Result<u8, AsmError> {
+        Ok(self.value(expr, -0x80, 0xFF)? as u8)
+    }
+
+    fn addr(&self, expr: &str) -> Result<u12, AsmError> {
+        Ok(u12::new(self.value(expr, 0, 0xFFF)? as u16))
+    }
+
+    fn nibble(&self, expr: &str) -> Result<u4, AsmError> {
+        Ok(u4::new(self.value(expr, 0, 0xF)? as u8))
+    }
+
+    /// Encode an instruction, along with the 16-bit address following `LD I, LONG`
+    fn inst(&self, mnemonic: &str, operands: &[Operand]) -> Result<(Inst, Option<u16>), AsmError> {
+        use Operand::*;
+
+        let inst = match (mnemonic, operands) {
+            ("CLS", []) => Inst::CLS,
+            ("RET", []) => Inst::RET,
+            ("SCR", []) => Inst::SCR,
+            ("SCL", []) => Inst::SCL,
+            ("EXIT", []) => Inst::EXIT,
+            ("LOW", []) => Inst::LOW,
+            ("HIGH", []) => Inst::HIGH,
+            ("AUDIO", []) => Inst::AUDIO,
+            ("SYS", [Value(addr)]) => Inst::SYS(self.addr(addr)?),
+            ("JP", [Value(addr)]) => Inst::JP(self.addr(addr)?),
+            ("JP", [V(reg), Value(addr)]) if reg.value() == 0 => Inst::JPV(self.addr(addr)?),
+            ("CALL", [Value(addr)]) => Inst::CALL(self.addr(addr)?),
+            ("SE", [V(x), V(y)]) => Inst::SEV(*x, *y),
+            ("SE", [V(x), Value(val)]) => Inst::SE(*x, self.byte(val)?),
+            ("SNE", [V(x), V(y)]) => Inst::SNEV(*x, *y),
+            ("SNE", [V(x), Value(val)]) => Inst::SNE(*x, self.byte(val)?),
+            ("LD", [V(x), V(y)]) => Inst::LDV(*x, *y),
+            ("LD", [V(x), Value(val)]) => Inst::LD(*x, self.byte(val)?),
+            ("LD", [I, Value(addr)]) => Inst::LDI(self.addr(addr)?),
+            ("LD", [I, Long(addr)]) => {
+                let addr = self.value(addr, 0, 0xFFFF)? as u16;
+                return Ok((Inst::LDIL, Some(addr)));
+            }
+            ("LD", [V(x), DT]) => Inst::LDVDT(*x),
+            ("LD", [V(x), K]) => Inst::LDVKEY(*x),
+            ("LD", [DT, V(x)]) => Inst::LDDTV(*x),
+            ("LD", [ST, V(x)]) => Inst::LDSTV(*x),
+            ("LD", [F, V(x)]) => Inst::LDFV(*x),
+            ("LD", [HF, V(x)]) => Inst::LDHFV(*x),
+            ("LD", [B, V(x)]) => Inst::LDBV(*x),
+            ("LD", [IndirectI, V(x)]) => Inst::LDIV(*x),
+            ("LD", [V(x), IndirectI]) => Inst::LDVI(*x),
+            ("LD", [R, V(x)]) => Inst::LDRV(*x),
+            ("LD", [V(x), R]) => Inst::LDVR(*x),
+            ("ADD", [V(x), V(y)]) => Inst::ADDV(*x, *y),
+            ("ADD", [V(x), Value(val)]) => Inst::ADD(*x, self.byte(val)?),
+            ("ADD", [I, V(x)]) => Inst::ADDIV(*x),
+            ("OR", [V(x), V(y)]) => Inst::OR(*x, *y),
+            ("AND", [V(x), V(y)]) => Inst::AND(*x, *y),
+            ("XOR", [V(x), V(y)]) => Inst::XOR(*x, *y),
+            ("SUB", [V(x), V(y)]) => Inst::SUB(*x, *y),
+            ("SUBN", [V(x), V(y)]) => Inst::SUBN(*x, *y),
+            ("SHR", [V(x)]) => Inst::SHR(*x, *x),
+            ("SHR", [V(x), V(y)]) => Inst::SHR(*x, *y),
+            ("SHL", [V(x)]) => Inst::SHL(*x, *x),
+            ("SHL", [V(x), V(y)]) => Inst::SHL(*x, *y),
+            ("RND", [V(x), Value(val)]) => Inst::RND(*x, self.byte(val)?),
+            ("DRW", [V(x), V(y), Value(n)]) => Inst::DRW(*x, *y, self.nibble(n)?),
+            ("SKP", [V(x)]) => Inst::SKP(*x),
+            ("SKNP", [V(x)]) => Inst::SKNP(*x),
+            ("SCD", [Value(n)]) => Inst::SCD(self.nibble(n)?),
+            ("SCU", [Value(n)]) => Inst::SCU(self.nibble(n)?),
+            ("SAVE", [V(x), V(y)]) => Inst::SAVE(*x, *y),
+            ("LOAD", [V(x), V(y)]) => Inst::LOAD(*x, *y),
+            ("PLANE", [Value(n)]) => Inst::PLANE(self.nibble(n)?),
+            ("PITCH", [V(x)]) => Inst::PITCH(*x),
+            _ if MNEMONICS.contains(&mnemonic) => {
+                return self.error(format!("invalid operands for {}", mnemonic));
+            }
+            _ => return self.error(format!("unknown instruction '{}'", mnemonic)),
+        };
+
+        Ok((inst, None))
+    }
+}
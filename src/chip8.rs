@@ -1,29 +1,66 @@
+mod state;
+
+use std::fs;
 use std::time::Instant;
 
 use arbitrary_int::u4;
 
+use crate::debugger::{Access, Action, Debugger};
 use crate::instructions::Inst;
-use crate::graphics::Drawable;
+use crate::graphics::{Drawable, Command};
+use crate::error::{Chip8Error, Fault};
+use crate::quirks::Quirks;
+use crate::rewind::RewindBuffer;
+use crate::rng::Rng;
+use crate::scheduler::{self, FrameStats, Scheduler, FRAME_RATE};
+use crate::trace::{Snapshot, Tracer};
 
 pub struct Chip8<T: Drawable> {
-    memory: [u8; 4096],
+    memory: [u8; MEMORY_SIZE],
     stack: [u16; 16],
     reg: Registers,
     gfx: T,
     vram: Vec<Vec<u8>>,
-    clock_timeout_nanos: u128,
+    scheduler: Scheduler,
+    quirks: Quirks,
+    drawn_this_frame: bool,
+    hires: bool,
+    vip_hires: bool,
+    halted: bool,
+    /// Key pressed during FX0A, which completes once it is released
+    key_wait: Option<u8>,
+    /// The last instruction didn't complete and rewound the PC to be executed again
+    retrying: bool,
+    rpl: [u8; 16],
+    planes: u8,
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    rom_hash: u64,
+    state_path: Option<String>,
+    rewind: RewindBuffer,
+    rewinding: bool,
+    paused: bool,
+    /// Run a single frame while paused
+    frame_advance: bool,
+    turbo: bool,
+    turbo_speed: f64,
+    slow_motion: bool,
+    rng: Rng,
+    debugger: Option<Debugger>,
+    tracer: Option<Tracer>,
 }
 
-struct Registers {
+#[derive(Debug, Clone)]
+pub struct Registers {
     // General purpose registers
-    v: [u8; 16],
+    pub v: [u8; 16],
 
     // Special purpose registers
-    pc: u16,
-    i: u16,
-    sp: u8,
-    dt: u8,
-    st: u8,
+    pub pc: u16,
+    pub i: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
 }
 
 impl Registers {
@@ -43,115 +80,654 @@ impl Registers {
 impl<T: Drawable> Chip8<T> {
     fn init(&mut self) {
         self.memory[0..HEX_SPRITES.len()].copy_from_slice(&HEX_SPRITES);
+        self.memory[BIG_HEX_SPRITES_ADDR..BIG_HEX_SPRITES_ADDR + BIG_HEX_SPRITES.len()].copy_from_slice(&BIG_HEX_SPRITES);
         self.reg.pc = 0x200;
     }
 
-    pub fn new(freq: usize, graphics: T) -> Chip8<T> {
-        let cols = graphics.width();
-        let rows = graphics.height();
-
+    pub fn new(freq: usize, graphics: T, quirks: Quirks) -> Chip8<T> {
         let mut c8 = Chip8 {
-            memory: [0; 4096],
+            memory: [0; MEMORY_SIZE],
             stack: [0; 16],
             reg: Registers::new(),
             gfx: graphics,
-            vram: vec![vec![0; cols]; rows],
-            clock_timeout_nanos: 1_000_000_000_u128.checked_div(freq as u128).unwrap_or(0),
+            vram: vec![vec![0; LORES.0]; LORES.1],
+            scheduler: Scheduler::new(freq),
+            quirks,
+            drawn_this_frame: false,
+            hires: false,
+            vip_hires: false,
+            halted: false,
+            key_wait: None,
+            retrying: false,
+            rpl: [0; 16],
+            planes: PLANE_1,
+            audio_pattern: DEFAULT_AUDIO_PATTERN,
+            pitch: DEFAULT_PITCH,
+            rom_hash: state::hash_rom(&[]),
+            state_path: None,
+            rewind: RewindBuffer::new(0),
+            rewinding: false,
+            paused: false,
+            frame_advance: false,
+            turbo: false,
+            turbo_speed: DEFAULT_TURBO_SPEED,
+            slow_motion: false,
+            rng: Rng::new(rand::random()),
+            debugger: None,
+            tracer: None,
         };
 
         c8.init();
         c8
     }
 
-    pub fn with_rom(freq: usize, graphics: T, rom: &[u8]) -> Chip8<T> {
-        let mut c8 = Chip8::new(freq, graphics);
-        c8.load_rom(rom);
-        c8
+    pub fn with_rom(freq: usize, graphics: T, quirks: Quirks, rom: &[u8]) -> Result<Chip8<T>, Chip8Error> {
+        let mut c8 = Chip8::new(freq, graphics, quirks);
+        c8.load_rom(rom)?;
+        Ok(c8)
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) {
+    /// Copy `rom` to memory at 0x200, failing if it doesn't fit
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let max = self.memory.len() - 0x200;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { len: rom.len(), max });
+        }
         self.memory[0x200..0x200 + rom.len()].copy_from_slice(rom);
+        self.rom_hash = state::hash_rom(rom);
+        Ok(())
+    }
+
+    /// Reseed the random number generator used by RND, making the run reproducible
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Hash identifying the loaded ROM
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Enable the save state slots of the run loop, stored as `<path>.state<slot>`.
+    /// Usually `path` is the path of the ROM
+    pub fn set_state_path(&mut self, path: &str) {
+        self.state_path = Some(path.to_string());
+    }
+
+    /// Attach a debugger to the run loop, which is consulted before every instruction
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    /// Log every instruction executed from now on
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
     }
 
-    fn fetch(&mut self) -> u16 {
+    fn fetch(&mut self) -> Result<u16, Chip8Error> {
         let pc = self.reg.pc as usize;
-        self.reg.pc += 2;
-        (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16
+        if pc + 1 >= self.memory.len() {
+            return Err(Chip8Error::PcOutOfBounds { pc: self.reg.pc });
+        }
+
+        self.reg.pc = self.reg.pc.wrapping_add(2);
+        Ok((self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16)
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.reg
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.reg
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn vram(&self) -> &[Vec<u8>] {
+        &self.vram
+    }
+
+    /// Whether the SUPER-CHIP 128x64 high resolution mode is active
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Whether the 64x64 two-page hires mode of the COSMAC VIP is active
+    pub fn is_vip_hires(&self) -> bool {
+        self.vip_hires
+    }
+
+    /// Force the 64x64 two-page hires mode of the COSMAC VIP, clearing the screen.
+    /// Programs starting with the standard `1260` hires entry point switch to it automatically
+    pub fn set_vip_hires(&mut self, vip_hires: bool) {
+        self.vip_hires = vip_hires;
+        self.reset_vram();
+    }
+
+    /// Whether the program terminated through the SUPER-CHIP EXIT instruction
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Whether the last instruction is waiting, for a key with FX0A or for the next frame to draw, and
+    /// will be executed again from the same address
+    pub fn is_retrying(&self) -> bool {
+        self.retrying
+    }
+
+    /// SUPER-CHIP RPL user flags, saved by FX75 and restored by FX85.
+    /// They are meant to outlive the program, so frontends should persist them between runs
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.rpl = flags;
     }
 
-    fn step(&mut self) {
-        let opcode = self.fetch();
-        let inst = Inst::decode(opcode).expect("Invalid opcode");
-        self.execute(inst);
+    pub fn graphics(&self) -> &T {
+        &self.gfx
+    }
+
+    pub fn graphics_mut(&mut self) -> &mut T {
+        &mut self.gfx
+    }
+
+    /// Fetch, decode and execute a single instruction
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if self.halted {
+            return Ok(());
+        }
+
+        let Some(mut tracer) = self.tracer.take() else {
+            return self.execute_next();
+        };
+        let before = self.snapshot();
+        let result = self.execute_next();
+        match tracer.record(&before, &self.reg, &self.stack, &self.memory, &result) {
+            Ok(()) => self.tracer = Some(tracer),
+            Err(err) => eprintln!("Failed to write the trace, tracing stopped: {}", err),
+        }
+        result
+    }
+
+    fn execute_next(&mut self) -> Result<(), Chip8Error> {
+        let pc = self.reg.pc;
+        self.retrying = false;
+        let mut opcode = self.fetch()?;
+
+        // Hires programs for the COSMAC VIP start by jumping to a patched interpreter bundled with the ROM,
+        // the CHIP-8 program itself starts at 0x2C0 and expects a 64x64 display
+        if pc == 0x200 && opcode == 0x1260 {
+            self.set_vip_hires(true);
+            opcode = 0x12C0;
+        }
+        let inst = Inst::decode(opcode).ok_or(Chip8Error::InvalidOpcode { pc, opcode })?;
+        self.execute(inst).map_err(|fault| fault.at(pc, opcode))
+    }
+
+    /// Decrement the delay and sound timers by one tick (1/60th of a second) and update the sound accordingly
+    pub fn tick_timers(&mut self) {
+        self.reg.dt = self.reg.dt.saturating_sub(1);
+        self.reg.st = self.reg.st.saturating_sub(1);
+        self.drawn_this_frame = false;
+
+        if self.reg.st == 0 {
+            self.gfx.sound_pause();
+        } else {
+            self.gfx.sound_resume();
+        }
     }
 
-    pub fn run(&mut self) {
+    /// Run a single frame without any timing: poll the frontend for input, execute `cycles` instructions,
+    /// tick the timers and present the screen.
+    /// Useful to drive the emulator from tests or from a frontend with its own main loop
+    pub fn run_frame(&mut self, cycles: usize) -> Result<(), Chip8Error> {
+        self.gfx.update(0);
+        for _ in 0..cycles {
+            self.step()?;
+        }
+        self.end_frame();
+        self.gfx.draw_screen(&self.vram);
+        Ok(())
+    }
+
+    /// End a frame whose instructions were executed through `step`: poll the frontend for input, tick
+    /// the timers and present the screen
+    pub fn finish_frame(&mut self) {
+        self.gfx.update(0);
+        self.end_frame();
+        self.gfx.draw_screen(&self.vram);
+    }
+
+    /// Keep the state of the last `frames` frames to allow rewinding, 0 disables rewinding
+    pub fn set_rewind_depth(&mut self, frames: usize) {
+        self.rewind = RewindBuffer::new(frames);
+    }
+
+    /// Go back to the state at the end of the previous recorded frame.
+    /// Returns false if there are no more frames to go back to, or the recorded state couldn't be loaded
+    pub fn rewind_frame(&mut self) -> bool {
+        let Some(state) = self.rewind.pop() else { return false };
+        if let Err(err) = self.load_state(&state) {
+            eprintln!("Failed to rewind: {}", err);
+            return false;
+        }
+        true
+    }
+
+    fn end_frame(&mut self) {
+        self.tick_timers();
+        if self.rewind.capacity() > 0 {
+            self.rewind.push(self.save_state());
+        }
+    }
+
+    /// Clock frequency in Hz, 0 if running as fast as possible
+    pub fn freq(&self) -> usize {
+        self.scheduler.freq()
+    }
+
+    /// Change the clock frequency, which also works while running
+    pub fn set_freq(&mut self, freq: usize) {
+        self.scheduler.set_freq(freq);
+    }
+
+    /// Speed multiplier while turbo is held, 0 runs as fast as possible
+    pub fn set_turbo_speed(&mut self, speed: f64) {
+        self.turbo_speed = speed;
+    }
+
+    /// Emulation speed measured while running through `run`, refreshed every second
+    pub fn frame_stats(&self) -> &FrameStats {
+        self.scheduler.stats()
+    }
+
+    pub fn run(&mut self) -> Result<(), Chip8Error> {
         self.gfx.init();
-        let mut timers = Instant::now();
-        let mut clock = Instant::now();
-        
-        while !self.gfx.should_close() {
-            loop {
-                let remaining_time = self.clock_timeout_nanos.saturating_sub(clock.elapsed().as_nanos()) / 1000000;
-                
-                self.gfx.update(remaining_time as u32);
-                self.gfx.draw_screen(&self.vram);
+        let result = self.run_loop();
+        self.gfx.finalize();
+        result
+    }
 
-                // Check for timers updates
-                if timers.elapsed().as_millis() >= (1000 / 60) {
-                    self.reg.dt = self.reg.dt.saturating_sub(1);
-                    self.reg.st = self.reg.st.saturating_sub(1);
-                    timers = Instant::now();
+    fn run_loop(&mut self) -> Result<(), Chip8Error> {
+        self.scheduler.reset();
 
-                    if self.reg.st == 0 {
-                        self.gfx.sound_pause();
-                    } else {
-                        self.gfx.sound_resume();
+        while !self.gfx.should_close() && !self.halted {
+            self.gfx.update(0);
+            while let Some(command) = self.gfx.poll_command() {
+                self.handle_command(command);
+            }
+
+            if self.paused {
+                if !self.frame_advance {
+                    self.gfx.draw_screen(&self.vram);
+                    scheduler::sleep_until(Instant::now() + scheduler::FRAME_DURATION);
+                    continue;
+                }
+                // Run the frame as if it was on time, however long the emulator was paused for
+                self.frame_advance = false;
+                self.scheduler.reset();
+            }
+
+            // While rewinding go back one frame instead of running one
+            let executed = if self.rewinding {
+                self.rewind_frame();
+                0
+            } else {
+                let mut frame = self.scheduler.begin_frame();
+                while !self.halted && frame.has_cycles() {
+                    // The debugger is taken out while it runs, as it needs to look at the whole machine
+                    if let Some(mut debugger) = self.debugger.take() {
+                        let stopped = Instant::now();
+                        let action = debugger.before_step(self);
+                        self.debugger = Some(debugger);
+                        if action == Action::Quit {
+                            return Ok(());
+                        }
+                        // Time spent at the prompt is not time the emulator fell behind
+                        self.scheduler.skip(stopped.elapsed());
                     }
+                    self.step()?;
+                    frame.executed += 1;
                 }
+                self.end_frame();
+                frame.executed
+            };
 
-                if clock.elapsed().as_nanos() >= self.clock_timeout_nanos {
-                    break;
+            // A single frame advanced while paused is always shown, and doesn't leave the sound on
+            let present = self.paused || self.scheduler.should_present();
+            if present {
+                self.gfx.draw_screen(&self.vram);
+            }
+            if self.paused {
+                self.gfx.sound_pause();
+            }
+            let next_frame = self.scheduler.end_frame(executed, present);
+            if self.scheduler.stats_updated() {
+                self.update_status();
+            }
+            scheduler::sleep_until(next_frame);
+        }
+
+        Ok(())
+    }
+
+    /// Show the speed mode, the clock frequency and the measured speed in the frontend
+    fn update_status(&mut self) {
+        let mut status = Vec::new();
+        if self.paused {
+            status.push("Paused".to_string());
+        } else if self.turbo {
+            status.push("Turbo".to_string());
+        } else if self.slow_motion {
+            status.push("Slow motion".to_string());
+        }
+        match self.freq() {
+            0 => status.push("Unlimited".to_string()),
+            freq => status.push(format!("{} Hz", freq)),
+        }
+        let stats = *self.scheduler.stats();
+        if !self.paused && stats.fps > 0.0 {
+            status.push(format!("{:.0}% speed, {:.0} fps", stats.speed() * 100.0, stats.presented_fps));
+        }
+        self.gfx.show_status(&status.join(" - "));
+    }
+
+    /// Run one more or one less instruction per frame, rounding to a whole number of instructions per frame.
+    /// Running as fast as possible is left as is
+    fn change_freq(&mut self, faster: bool) {
+        let freq = self.freq();
+        if freq == 0 {
+            return;
+        }
+        let frame_rate = FRAME_RATE as usize;
+        let cycles = if faster { freq / frame_rate + 1 } else { freq.div_ceil(frame_rate) - 1 };
+        self.set_freq(cycles.max(1) * frame_rate);
+        self.update_status();
+    }
+
+    /// Apply the speed of the current mode, turbo taking precedence over slow motion
+    fn update_speed(&mut self) {
+        let speed = if self.turbo {
+            self.turbo_speed
+        } else if self.slow_motion {
+            SLOW_MOTION_SPEED
+        } else {
+            1.0
+        };
+        self.scheduler.set_speed(speed);
+        self.update_status();
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Break => {
+                if let Some(debugger) = self.debugger.as_mut() {
+                    debugger.interrupt();
+                }
+            }
+            Command::Pause => {
+                self.paused = !self.paused;
+                if self.paused {
+                    self.gfx.sound_pause();
+                } else {
+                    self.scheduler.reset();
+                }
+                self.update_status();
+            }
+            Command::FrameAdvance => {
+                self.frame_advance = self.paused;
+            }
+            Command::Turbo(turbo) => {
+                self.turbo = turbo;
+                self.update_speed();
+            }
+            Command::SlowMotion => {
+                self.slow_motion = !self.slow_motion;
+                self.update_speed();
+            }
+            Command::IncreaseSpeed => {
+                self.change_freq(true);
+            }
+            Command::DecreaseSpeed => {
+                self.change_freq(false);
+            }
+            Command::Rewind(rewinding) => {
+                self.rewinding = rewinding;
+                if !rewinding {
+                    self.gfx.sound_pause();
+                }
+            }
+            Command::SaveState(slot) => {
+                if let Some(path) = self.state_slot_path(slot) {
+                    if let Err(err) = fs::write(&path, self.save_state()) {
+                        eprintln!("Failed to save state to {}: {}", path, err);
+                    }
                 }
             }
-            clock = Instant::now();
+            Command::LoadState(slot) => {
+                if let Some(path) = self.state_slot_path(slot) {
+                    match fs::read(&path) {
+                        Ok(state) => {
+                            if let Err(err) = self.load_state(&state) {
+                                eprintln!("Failed to load state from {}: {}", path, err);
+                            }
+                        }
+                        Err(err) => eprintln!("Failed to load state from {}: {}", path, err),
+                    }
+                }
+            }
+        }
+    }
+
+    fn state_slot_path(&self, slot: u8) -> Option<String> {
+        self.state_path.as_ref().map(|path| format!("{}.state{}", path, slot))
+    }
 
-            self.step();
+    /// Amount FX55/FX65 add to I after storing/loading V0 to VX
+    fn load_store_increment(&self, x: u4) -> u16 {
+        match (self.quirks.load_store_increments_i, self.quirks.load_store_increments_i_by_x) {
+            (false, _) => 0,
+            (true, true) => x.value() as u16,
+            (true, false) => x.value() as u16 + 1,
         }
-        self.gfx.finalize();
+    }
+
+    /// Returns the memory range of `len` bytes starting at `start`, or a fault if it doesn't fit in memory
+    fn mem_range(&self, start: u16, len: usize) -> Result<std::ops::Range<usize>, Fault> {
+        let start = start as usize;
+        if start + len > self.memory.len() {
+            return Err(Fault::MemoryOutOfBounds(start + len - 1));
+        }
+        Ok(start..start + len)
+    }
+
+    /// Memory that the next instruction will read or write, if any
+    pub fn next_memory_access(&self) -> Option<(std::ops::Range<usize>, Access)> {
+        let pc = self.reg.pc as usize;
+        let opcode = u16::from_be_bytes([*self.memory.get(pc)?, *self.memory.get(pc + 1)?]);
+
+        let (len, access) = match Inst::decode(opcode)? {
+            Inst::DRW(_, _, n) => {
+                let sprite_len = if n.value() == 0 { 32 } else { n.value() as usize };
+                let planes = (self.planes & (PLANE_1 | PLANE_2)).count_ones() as usize;
+                (sprite_len * planes, Access::Read)
+            }
+            Inst::LDBV(_) => (3, Access::Write),
+            Inst::LDIV(reg) => (reg.value() as usize + 1, Access::Write),
+            Inst::LDVI(reg) => (reg.value() as usize + 1, Access::Read),
+            Inst::SAVE(reg1, reg2) => (Self::reg_range(reg1, reg2).len(), Access::Write),
+            Inst::LOAD(reg1, reg2) => (Self::reg_range(reg1, reg2).len(), Access::Read),
+            Inst::AUDIO => (self.audio_pattern.len(), Access::Read),
+            _ => return None,
+        };
+
+        if len == 0 {
+            return None;
+        }
+        self.mem_range(self.reg.i, len).ok().map(|range| (range, access))
+    }
+
+    /// State that the tracer compares against after the next instruction
+    fn snapshot(&self) -> Snapshot {
+        let pc = self.reg.pc as usize;
+        let opcode = match (self.memory.get(pc), self.memory.get(pc + 1)) {
+            (Some(&high), Some(&low)) => u16::from_be_bytes([high, low]),
+            _ => 0,
+        };
+        let memory = match self.next_memory_access() {
+            Some((range, Access::Write)) => Some((range.start, self.memory[range].to_vec())),
+            _ => None,
+        };
+
+        Snapshot {
+            pc: self.reg.pc,
+            opcode,
+            reg: self.reg.clone(),
+            stack: self.stack,
+            memory,
+        }
+    }
+
+    /// Switch between the low and high resolution modes, clearing the screen
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.reset_vram();
+    }
+
+    /// Reallocate a blank vram for the current display mode
+    fn reset_vram(&mut self) {
+        let (width, height) = resolution(self.hires, self.vip_hires);
+        self.vram = vec![vec![0; width]; height];
+    }
 
+    /// Scroll the selected bitplanes by `dx` columns and `dy` rows, filling the uncovered area with blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let height = self.vram.len() as isize;
+        let width = self.vram[0].len() as isize;
+        let planes = self.planes;
+        let old = self.vram.clone();
+
+        for (y, row) in self.vram.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let (sx, sy) = (x as isize - dx, y as isize - dy);
+                let src = if (0..width).contains(&sx) && (0..height).contains(&sy) {
+                    old[sy as usize][sx as usize]
+                } else {
+                    0
+                };
+                *pixel = (*pixel & !planes) | (src & planes);
+            }
+        }
     }
 
-    fn drw(&mut self, reg1: u4, reg2: u4, n: u4) {
+    fn drw(&mut self, reg1: u4, reg2: u4, n: u4) -> Result<(), Fault> {
         let x = self.reg.v[reg1.value() as usize];
         let y = self.reg.v[reg2.value() as usize];
-        let sprite = self.memory[self.reg.i as usize..self.reg.i as usize + n.value() as usize].to_vec();
+
+        // DXY0 draws a 16x16 sprite made of two bytes per row
+        let (cols, rows) = if n.value() == 0 { (16, 16) } else { (8, n.value() as usize) };
+        let bytes_per_row = cols / 8;
+        let sprite_len = rows * bytes_per_row;
+
+        let height = self.vram.len();
+        let width = self.vram[0].len();
+
+        // The starting position always wraps, the sprite itself is either clipped or wrapped
+        let x = x as usize % width;
+        let y = y as usize % height;
 
         self.reg.v[0xF] = 0;
-        for (row, &byte) in sprite.iter().enumerate() {
-            let cy = (y as usize + row) % self.vram.len();
-            for col in 0..8 {
-                let cx = (x as usize + col) % self.vram[0].len();
-                let color = ((byte & (0b1000_0000 >> col)) > 0) as u8;
-                self.reg.v[0xF] |= (self.vram[cy][cx] != 0 && color != 0) as u8;
-                self.vram[cy][cx] ^= color;
+
+        // With both bitplanes selected, the sprite for the second plane follows the one for the first in memory
+        let mut addr = self.reg.i;
+        for plane in [PLANE_1, PLANE_2] {
+            if self.planes & plane == 0 {
+                continue;
+            }
+
+            let sprite: Vec<u16> = self.memory[self.mem_range(addr, sprite_len)?]
+                .chunks(bytes_per_row)
+                .map(|bytes| bytes.iter().fold(0, |line, &byte| line << 8 | byte as u16) << (16 - cols))
+                .collect();
+            addr = addr.wrapping_add(sprite_len as u16);
+
+            for (row, &line) in sprite.iter().enumerate() {
+                if self.quirks.clip_sprites && y + row >= height {
+                    break;
+                }
+                let cy = (y + row) % height;
+                for col in 0..cols {
+                    if self.quirks.clip_sprites && x + col >= width {
+                        break;
+                    }
+                    let cx = (x + col) % width;
+                    let color = ((line & (0x8000 >> col)) > 0) as u8 * plane;
+                    self.reg.v[0xF] |= (self.vram[cy][cx] & color != 0) as u8;
+                    self.vram[cy][cx] ^= color;
+                }
             }
         }
+
+        Ok(())
     }
 
-    fn execute(&mut self, inst: Inst) {
+    /// Skip the next instruction, taking into account the 4 bytes long F000 NNNN
+    fn skip(&mut self) {
+        let pc = self.reg.pc as usize;
+        let long = pc + 1 < self.memory.len() && self.memory[pc] == 0xF0 && self.memory[pc + 1] == 0x00;
+        self.reg.pc = self.reg.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    /// Registers from `reg1` to `reg2`, in descending order if `reg1` is greater than `reg2`
+    fn reg_range(reg1: u4, reg2: u4) -> Vec<usize> {
+        let (x, y) = (reg1.value() as usize, reg2.value() as usize);
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    fn execute(&mut self, inst: Inst) -> Result<(), Fault> {
         match inst {
-            Inst::SYS(_addr) => {
-                // Ignore
+            Inst::SYS(addr) => {
+                // The patched hires interpreter exposes its clear screen routine at 0x230
+                if self.vip_hires && addr.value() == 0x230 {
+                    self.vram.iter_mut().for_each(|row| row.fill(0));
+                }
             }
             Inst::CLS => {
                 for row in self.vram.iter_mut() {
                     for pixel in row {
-                        *pixel = 0;
+                        *pixel &= !self.planes;
                     }
                 }
             }
             Inst::RET => {
+                if self.reg.sp == 0 {
+                    return Err(Fault::StackUnderflow);
+                }
                 self.reg.sp -= 1;
                 self.reg.pc = self.stack[self.reg.sp as usize];
             }
@@ -159,23 +735,26 @@ impl<T: Drawable> Chip8<T> {
                 self.reg.pc = addr.value();
             }
             Inst::CALL(addr) => {
+                if self.reg.sp as usize >= self.stack.len() {
+                    return Err(Fault::StackOverflow);
+                }
                 self.stack[self.reg.sp as usize] = self.reg.pc;
                 self.reg.sp += 1;
                 self.reg.pc = addr.value();
             }
             Inst::SE(reg, val) => {
                 if self.reg.v[reg.value() as usize] == val {
-                    self.reg.pc += 2;
+                    self.skip();
                 }
             }
             Inst::SNE(reg, val) => {
                 if self.reg.v[reg.value() as usize] != val {
-                    self.reg.pc += 2;
+                    self.skip();
                 }
             }
             Inst::SEV(reg1, reg2) => {
                 if self.reg.v[reg1.value() as usize] == self.reg.v[reg2.value() as usize] {
-                    self.reg.pc += 2;
+                    self.skip();
                 }
             }
             Inst::LD(reg, val) => {
@@ -189,12 +768,21 @@ impl<T: Drawable> Chip8<T> {
             }
             Inst::OR(reg1, reg2) => {
                 self.reg.v[reg1.value() as usize] |= self.reg.v[reg2.value() as usize];
+                if self.quirks.vf_reset {
+                    self.reg.v[0xF] = 0;
+                }
             }
             Inst::AND(reg1, reg2) => {
                 self.reg.v[reg1.value() as usize] &= self.reg.v[reg2.value() as usize];
+                if self.quirks.vf_reset {
+                    self.reg.v[0xF] = 0;
+                }
             }
             Inst::XOR(reg1, reg2) => {
                 self.reg.v[reg1.value() as usize] ^= self.reg.v[reg2.value() as usize];
+                if self.quirks.vf_reset {
+                    self.reg.v[0xF] = 0;
+                }
             }
             Inst::ADDV(reg1, reg2) => {
                 let (val, overflow) = self.reg.v[reg1.value() as usize].overflowing_add(self.reg.v[reg2.value() as usize]);
@@ -204,55 +792,83 @@ impl<T: Drawable> Chip8<T> {
             Inst::SUB(reg1, reg2) => {
                 let val1 = self.reg.v[reg1.value() as usize];
                 let val2 = self.reg.v[reg2.value() as usize];
-                self.reg.v[0xF] = (val1 > val2) as u8;
                 self.reg.v[reg1.value() as usize] = val1.wrapping_sub(val2);
+                self.reg.v[0xF] = (val1 >= val2) as u8;
             }
-            Inst::SHR(reg1, _reg2) => {
-                self.reg.v[0xF] = self.reg.v[reg1.value() as usize] & 0x1;
-                self.reg.v[reg1.value() as usize] >>= 1;
+            Inst::SHR(reg1, reg2) => {
+                let src = if self.quirks.shift_uses_vy { reg2 } else { reg1 };
+                let val = self.reg.v[src.value() as usize];
+                self.reg.v[reg1.value() as usize] = val >> 1;
+                self.reg.v[0xF] = val & 0x1;
             }
             Inst::SUBN(reg1, reg2) => {
                 let val1 = self.reg.v[reg1.value() as usize];
                 let val2 = self.reg.v[reg2.value() as usize];
-                self.reg.v[0xF] = (val2 > val1) as u8;
                 self.reg.v[reg1.value() as usize] = val2.wrapping_sub(val1);
+                self.reg.v[0xF] = (val2 >= val1) as u8;
             }
-            Inst::SHL(reg1, _reg2) => {
-                self.reg.v[0xF] = self.reg.v[reg1.value() as usize] & 0b1000_0000;
-                self.reg.v[reg1.value() as usize] <<= 1;
+            Inst::SHL(reg1, reg2) => {
+                let src = if self.quirks.shift_uses_vy { reg2 } else { reg1 };
+                let val = self.reg.v[src.value() as usize];
+                self.reg.v[reg1.value() as usize] = val << 1;
+                self.reg.v[0xF] = val >> 7;
             }
             Inst::SNEV(reg1, reg2) => {
                 if self.reg.v[reg1.value() as usize] != self.reg.v[reg2.value() as usize] {
-                    self.reg.pc += 2;
+                    self.skip();
                 }
             }
             Inst::LDI(addr) => {
                 self.reg.i = addr.value();
             }
             Inst::JPV(addr) => {
-                self.reg.pc = addr.value() + self.reg.v[0] as u16;
+                let reg = if self.quirks.jump_uses_vx { (addr.value() >> 8) as usize } else { 0 };
+                self.reg.pc = addr.value() + self.reg.v[reg] as u16;
             }
             Inst::RND(reg, val) => {
-                self.reg.v[reg.value() as usize] = rand::random::<u8>() & val;
+                self.reg.v[reg.value() as usize] = self.rng.next_u8() & val;
             },
             Inst::DRW(reg1, reg2, val) => {
-                self.drw(reg1, reg2, val);
+                if self.quirks.display_wait && self.drawn_this_frame {
+                    // Retry the same instruction until the next frame starts
+                    self.reg.pc = self.reg.pc.wrapping_sub(2);
+                    self.retrying = true;
+                } else {
+                    self.drw(reg1, reg2, val)?;
+                    self.drawn_this_frame = true;
+                }
             },
             Inst::SKP(reg) => {
                 if self.gfx.is_key_pressed(self.reg.v[reg.value() as usize]) {
-                    self.reg.pc += 2;
+                    self.skip();
                 }
             },
             Inst::SKNP(reg) => {
                 if !self.gfx.is_key_pressed(self.reg.v[reg.value() as usize]) {
-                    self.reg.pc += 2;
+                    self.skip();
                 }
             },
             Inst::LDVDT(reg) => {
                 self.reg.v[reg.value() as usize] = self.reg.dt;
             },
             Inst::LDVKEY(reg) => {
-                self.reg.v[reg.value() as usize] = self.gfx.wait_for_key();
+                // As on the COSMAC VIP, wait for a key to be pressed and then released. The instruction is
+                // executed again until then, so the timers and the frontend keep running meanwhile
+                match self.key_wait {
+                    Some(key) if !self.gfx.is_key_pressed(key) => {
+                        self.reg.v[reg.value() as usize] = key;
+                        self.key_wait = None;
+                    }
+                    Some(_) => {
+                        self.reg.pc = self.reg.pc.wrapping_sub(2);
+                        self.retrying = true;
+                    }
+                    None => {
+                        self.key_wait = (0..16).find(|&key| self.gfx.is_key_pressed(key));
+                        self.reg.pc = self.reg.pc.wrapping_sub(2);
+                        self.retrying = true;
+                    }
+                }
             },
             Inst::LDDTV(reg) => {
                 self.reg.dt = self.reg.v[reg.value() as usize];
@@ -261,31 +877,127 @@ impl<T: Drawable> Chip8<T> {
                 self.reg.st = self.reg.v[reg.value() as usize];
             },
             Inst::ADDIV(reg) => {
-                self.reg.i += self.reg.v[reg.value() as usize] as u16;
+                self.reg.i = self.reg.i.wrapping_add(self.reg.v[reg.value() as usize] as u16);
             },
             Inst::LDFV(reg) => {
                 self.reg.i = self.reg.v[reg.value() as usize] as u16 * SPRITE_SIZE as u16;
             },
+            Inst::LDHFV(reg) => {
+                self.reg.i = BIG_HEX_SPRITES_ADDR as u16 + self.reg.v[reg.value() as usize] as u16 * BIG_SPRITE_SIZE as u16;
+            },
             Inst::LDBV(reg) => {
                 let val = self.reg.v[reg.value() as usize];
-                self.memory[self.reg.i as usize] = val / 100;
-                self.memory[self.reg.i as usize + 1] = (val / 10) % 10;
-                self.memory[self.reg.i as usize + 2] = val % 10;
+                let range = self.mem_range(self.reg.i, 3)?;
+                self.memory[range].copy_from_slice(&[val / 100, (val / 10) % 10, val % 10]);
             },
             Inst::LDIV(reg) => {
-                let dst = &mut self.memory[(self.reg.i as usize)..=(self.reg.i as usize + reg.value() as usize)];
+                let range = self.mem_range(self.reg.i, reg.value() as usize + 1)?;
+                let dst = &mut self.memory[range];
                 let src = &self.reg.v[0..=reg.value() as usize];
                 dst.copy_from_slice(src);
+                self.reg.i = self.reg.i.wrapping_add(self.load_store_increment(reg));
             },
             Inst::LDVI(reg) => {
-                let src = &self.memory[(self.reg.i as usize)..=(self.reg.i as usize + reg.value() as usize)];
+                let range = self.mem_range(self.reg.i, reg.value() as usize + 1)?;
+                let src = &self.memory[range];
                 let dst = &mut self.reg.v[0..=reg.value() as usize];
                 dst.copy_from_slice(src);
+                self.reg.i = self.reg.i.wrapping_add(self.load_store_increment(reg));
+            },
+            Inst::SCD(n) => {
+                self.scroll(0, n.value() as isize);
+            },
+            Inst::SCR => {
+                self.scroll(4, 0);
+            },
+            Inst::SCL => {
+                self.scroll(-4, 0);
+            },
+            Inst::EXIT => {
+                self.halted = true;
+            },
+            Inst::LOW => {
+                self.set_hires(false);
+            },
+            Inst::HIGH => {
+                self.set_hires(true);
+            },
+            Inst::LDRV(reg) => {
+                self.rpl[0..=reg.value() as usize].copy_from_slice(&self.reg.v[0..=reg.value() as usize]);
+            },
+            Inst::LDVR(reg) => {
+                self.reg.v[0..=reg.value() as usize].copy_from_slice(&self.rpl[0..=reg.value() as usize]);
+            },
+            Inst::SCU(n) => {
+                self.scroll(0, -(n.value() as isize));
+            },
+            Inst::SAVE(reg1, reg2) => {
+                let regs = Self::reg_range(reg1, reg2);
+                let range = self.mem_range(self.reg.i, regs.len())?;
+                for (addr, reg) in range.zip(regs) {
+                    self.memory[addr] = self.reg.v[reg];
+                }
+            },
+            Inst::LOAD(reg1, reg2) => {
+                let regs = Self::reg_range(reg1, reg2);
+                let range = self.mem_range(self.reg.i, regs.len())?;
+                for (addr, reg) in range.zip(regs) {
+                    self.reg.v[reg] = self.memory[addr];
+                }
+            },
+            Inst::LDIL => {
+                let range = self.mem_range(self.reg.pc, 2)?;
+                self.reg.i = u16::from_be_bytes([self.memory[range.start], self.memory[range.start + 1]]);
+                self.reg.pc = self.reg.pc.wrapping_add(2);
+            },
+            Inst::PLANE(n) => {
+                self.planes = n.value() & (PLANE_1 | PLANE_2);
+            },
+            Inst::AUDIO => {
+                let range = self.mem_range(self.reg.i, self.audio_pattern.len())?;
+                self.audio_pattern.copy_from_slice(&self.memory[range]);
+                self.gfx.sound_pattern(&self.audio_pattern, self.pitch);
+            },
+            Inst::PITCH(reg) => {
+                self.pitch = self.reg.v[reg.value() as usize];
+                self.gfx.sound_pattern(&self.audio_pattern, self.pitch);
             },
         }
+
+        Ok(())
     }
 }
 
+const MEMORY_SIZE: usize = 0x10000;
+
+// Display resolutions, as (width, height)
+const LORES: (usize, usize) = (64, 32);
+const HIRES: (usize, usize) = (128, 64);
+const VIP_HIRES: (usize, usize) = (64, 64);
+
+/// Resolution of the display mode, SUPER-CHIP hires taking precedence over the VIP one
+fn resolution(hires: bool, vip_hires: bool) -> (usize, usize) {
+    if hires {
+        HIRES
+    } else if vip_hires {
+        VIP_HIRES
+    } else {
+        LORES
+    }
+}
+
+// XO-CHIP bitplanes, each pixel in vram is a bitmask of the planes it is set in
+const PLANE_1: u8 = 0b01;
+const PLANE_2: u8 = 0b10;
+
+// Square wave played until the program loads its own XO-CHIP audio pattern
+const DEFAULT_AUDIO_PATTERN: [u8; 16] = [0xF0; 16];
+const DEFAULT_PITCH: u8 = 64;
+
+// Speed multipliers of the turbo and slow motion modes
+const DEFAULT_TURBO_SPEED: f64 = 4.0;
+const SLOW_MOTION_SPEED: f64 = 0.25;
+
 const SPRITE_SIZE: usize = 5;
 const HEX_SPRITES: [u8; 5 * 16] = [
     // 0
@@ -336,3 +1048,55 @@ const HEX_SPRITES: [u8; 5 * 16] = [
     // F
     0xF0, 0x80, 0xF0, 0x80, 0x80,
 ];
+
+const BIG_SPRITE_SIZE: usize = 10;
+const BIG_HEX_SPRITES_ADDR: usize = HEX_SPRITES.len();
+const BIG_HEX_SPRITES: [u8; 10 * 16] = [
+    // 0
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C,
+
+    // 1
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C,
+
+    // 2
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF,
+
+    // 3
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C,
+
+    // 4
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06,
+
+    // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C,
+
+    // 6
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C,
+
+    // 7
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60,
+
+    // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C,
+
+    // 9
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C,
+
+    // A
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+
+    // B
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC,
+
+    // C
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C,
+
+    // D
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+
+    // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF,
+
+    // F
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0,
+];
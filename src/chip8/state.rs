@@ -0,0 +1,174 @@
+use crate::error::StateError;
+use crate::graphics::Drawable;
+
+use super::{resolution, Chip8, PLANE_1, PLANE_2};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u16 = 1;
+
+/// Value of the FX0A key in the state when no key is held
+const NO_KEY: u8 = 0xFF;
+
+/// FNV-1a hash, used to tie save states to the ROM they were taken from
+pub(crate) fn hash_rom(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+impl<T: Drawable> Chip8<T> {
+    /// Serialize the complete machine state.
+    ///
+    /// The format is versioned and starts with the hash of the loaded ROM, all integers are big endian
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + 1024);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_be_bytes());
+        out.extend_from_slice(&self.rom_hash.to_be_bytes());
+
+        out.extend_from_slice(&self.memory);
+        for addr in self.stack {
+            out.extend_from_slice(&addr.to_be_bytes());
+        }
+
+        out.extend_from_slice(&self.reg.v);
+        out.extend_from_slice(&self.reg.pc.to_be_bytes());
+        out.extend_from_slice(&self.reg.i.to_be_bytes());
+        out.extend_from_slice(&[self.reg.sp, self.reg.dt, self.reg.st]);
+
+        out.extend_from_slice(&self.rng.state().to_be_bytes());
+
+        out.extend_from_slice(&[
+            self.hires as u8,
+            self.vip_hires as u8,
+            self.halted as u8,
+            self.drawn_this_frame as u8,
+            self.planes,
+            self.key_wait.unwrap_or(NO_KEY),
+        ]);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+
+        out.extend_from_slice(&(self.vram[0].len() as u16).to_be_bytes());
+        out.extend_from_slice(&(self.vram.len() as u16).to_be_bytes());
+        for row in self.vram.iter() {
+            out.extend_from_slice(row);
+        }
+
+        out
+    }
+
+    /// Restore a state produced by `save_state`. The machine is left untouched if the state is invalid
+    /// or was taken with a different ROM
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader { data: state };
+
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if reader.u64()? != self.rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+
+        let memory = reader.bytes(self.memory.len())?;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = reader.u16()?;
+        }
+
+        let mut reg = self.reg.clone();
+        reg.v.copy_from_slice(reader.bytes(16)?);
+        reg.pc = reader.u16()?;
+        reg.i = reader.u16()?;
+        reg.sp = reader.u8()?;
+        reg.dt = reader.u8()?;
+        reg.st = reader.u8()?;
+        if reg.sp as usize > stack.len() {
+            return Err(StateError::Corrupted);
+        }
+
+        let rng_state = reader.u64()?;
+
+        let hires = reader.u8()? != 0;
+        let vip_hires = reader.u8()? != 0;
+        let halted = reader.u8()? != 0;
+        let drawn_this_frame = reader.u8()? != 0;
+        let planes = reader.u8()?;
+        let key_wait = Some(reader.u8()?).filter(|&key| key != NO_KEY);
+        if planes & !(PLANE_1 | PLANE_2) != 0 || key_wait.is_some_and(|key| key > 0xF) {
+            return Err(StateError::Corrupted);
+        }
+        let audio_pattern: [u8; 16] = reader.bytes(16)?.try_into().unwrap();
+        let pitch = reader.u8()?;
+
+        let width = reader.u16()? as usize;
+        let height = reader.u16()? as usize;
+        if (width, height) != resolution(hires, vip_hires) {
+            return Err(StateError::Corrupted);
+        }
+        let vram: Vec<Vec<u8>> = reader.bytes(width * height)?
+            .chunks(width)
+            .map(|row| row.to_vec())
+            .collect();
+        if vram.iter().flatten().any(|&pixel| pixel & !(PLANE_1 | PLANE_2) != 0) {
+            return Err(StateError::Corrupted);
+        }
+
+        if !reader.data.is_empty() {
+            return Err(StateError::Corrupted);
+        }
+
+        // The state is valid, apply it all at once
+        self.memory.copy_from_slice(memory);
+        self.stack = stack;
+        self.reg = reg;
+        self.rng.set_state(rng_state);
+        self.hires = hires;
+        self.vip_hires = vip_hires;
+        self.halted = halted;
+        self.drawn_this_frame = drawn_this_frame;
+        self.planes = planes;
+        self.key_wait = key_wait;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.vram = vram;
+
+        self.gfx.sound_pattern(&self.audio_pattern, self.pitch);
+        if self.reg.st == 0 {
+            self.gfx.sound_pause();
+        } else {
+            self.gfx.sound_resume();
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,540 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+
+use crate::chip8::Chip8;
+use crate::graphics::Drawable;
+use crate::disasm;
+
+/// Kind of memory access made by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Register that can be used in a breakpoint condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+/// Condition on the value of a register, such as `v3 == 5`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub reg: Register,
+    pub cmp: Comparison,
+    pub value: u16,
+}
+
+/// Stop before executing the instruction at `addr` when the condition holds. Without an address, stop
+/// before the first instruction for which the condition holds after not holding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: Option<u16>,
+    pub condition: Option<Condition>,
+}
+
+/// Stop before an instruction reads or writes, as selected by `read` and `write`, the given addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addrs: RangeInclusive<usize>,
+    pub read: bool,
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Running,
+    /// Stop after the given number of instructions
+    Step(usize),
+    /// Stop once the stack pointer is back at or below the given level, so calls are executed entirely
+    StepOver(u8),
+    /// Stop once the stack pointer is below the given level, after returning from the current subroutine
+    StepOut(u8),
+}
+
+/// What the run loop should do once the debugger gives control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Resume,
+    Quit,
+}
+
+/// Interactive debugger, driven through a command prompt on stdin.
+///
+/// The run loop calls `before_step` before executing each instruction, which stops at breakpoints,
+/// watchpoints and after stepping, and then reads commands until execution is resumed. Type `help`
+/// at the prompt for the list of commands
+pub struct Debugger {
+    breakpoints: Vec<(usize, Breakpoint)>,
+    /// Breakpoints without an address whose condition held before the last instruction
+    conditions_held: BTreeSet<usize>,
+    watchpoints: Vec<(usize, Watchpoint)>,
+    next_id: usize,
+    mode: Mode,
+    last_command: String,
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    /// Create a debugger that stops before the first instruction
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            conditions_held: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            next_id: 1,
+            mode: Mode::Step(1),
+            last_command: String::new(),
+        }
+    }
+
+    /// Add a breakpoint, returning its id
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        let id = self.new_id();
+        self.breakpoints.push((id, breakpoint));
+        id
+    }
+
+    /// Add a watchpoint, returning its id
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let id = self.new_id();
+        self.watchpoints.push((id, watchpoint));
+        id
+    }
+
+    /// Remove the breakpoint or watchpoint with the given id, returning false if there is none
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.breakpoints.len() + self.watchpoints.len();
+        self.breakpoints.retain(|&(bp, _)| bp != id);
+        self.conditions_held.remove(&id);
+        self.watchpoints.retain(|&(wp, _)| wp != id);
+        len != self.breakpoints.len() + self.watchpoints.len()
+    }
+
+    /// Stop before the next instruction
+    pub fn interrupt(&mut self) {
+        self.mode = Mode::Step(1);
+    }
+
+    /// Run until the next breakpoint or watchpoint
+    pub fn resume(&mut self) {
+        self.mode = Mode::Running;
+    }
+
+    fn new_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Check whether to stop before the next instruction of `chip8`, and if so prompt for commands
+    pub fn before_step<T: Drawable>(&mut self, chip8: &Chip8<T>) -> Action {
+        match self.stop_reason(chip8) {
+            Some(reason) => {
+                if !reason.is_empty() {
+                    println!("{}", reason);
+                }
+                self.resume();
+                self.prompt(chip8)
+            }
+            None => Action::Resume,
+        }
+    }
+
+    /// Why execution should stop before the next instruction, if it should. The reason is empty when
+    /// stopping after stepping
+    pub fn stop_reason<T: Drawable>(&mut self, chip8: &Chip8<T>) -> Option<String> {
+        let reg = chip8.registers();
+        // Breakpoints are checked even while stepping, to notice conditions starting to hold meanwhile
+        let breakpoint = if chip8.is_retrying() { None } else { self.breakpoint_hit(chip8) };
+        match self.mode {
+            Mode::Running => (),
+            Mode::Step(1) => return Some(String::new()),
+            Mode::Step(count) => self.mode = Mode::Step(count - 1),
+            Mode::StepOver(sp) if reg.sp <= sp => return Some(String::new()),
+            Mode::StepOut(sp) if reg.sp < sp => return Some(String::new()),
+            Mode::StepOver(_) | Mode::StepOut(_) => (),
+        }
+
+        // An instruction that is waiting runs again from the same address, it was already checked before
+        // its first attempt and stopping on every retry would never let it complete
+        if chip8.is_retrying() {
+            return None;
+        }
+
+        if breakpoint.is_some() {
+            return breakpoint;
+        }
+
+        if let Some((range, access)) = chip8.next_memory_access() {
+            for (id, watchpoint) in self.watchpoints.iter() {
+                let watched = match access {
+                    Access::Read => watchpoint.read,
+                    Access::Write => watchpoint.write,
+                };
+                let overlaps = range.start <= *watchpoint.addrs.end() && *watchpoint.addrs.start() < range.end;
+                if watched && overlaps {
+                    let kind = if access == Access::Read { "read" } else { "write" };
+                    return Some(format!(
+                        "Watchpoint {}: {} of 0x{:03X}-0x{:03X} at 0x{:03X}", id, kind, range.start, range.end - 1, reg.pc
+                    ));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Reason to stop at the first breakpoint hit before the next instruction, if any. Breakpoints without
+    /// an address are only hit when their condition starts holding, so continuing gets past them
+    fn breakpoint_hit<T: Drawable>(&mut self, chip8: &Chip8<T>) -> Option<String> {
+        let pc = chip8.registers().pc;
+        let mut hit = None;
+        for &(id, ref breakpoint) in self.breakpoints.iter() {
+            let condition = breakpoint.condition.is_none_or(|condition| condition.holds(chip8));
+            let stop = match breakpoint.addr {
+                Some(addr) => addr == pc && condition,
+                // Every one is updated, even after a hit, to know which conditions held at the next instruction
+                None if condition => self.conditions_held.insert(id),
+                None => {
+                    self.conditions_held.remove(&id);
+                    false
+                }
+            };
+            if stop && hit.is_none() {
+                hit = Some(format!("Breakpoint {} at 0x{:03X}", id, pc));
+            }
+        }
+        hit
+    }
+
+    fn prompt<T: Drawable>(&mut self, chip8: &Chip8<T>) -> Action {
+        print_registers(chip8);
+        print_disassembly(chip8, chip8.registers().pc, 4, 6);
+
+        let stdin = io::stdin();
+        loop {
+            print!("(chip8) ");
+            io::stdout().flush().unwrap();
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                // End of input, there is no one left to debug the program
+                return Action::Quit;
+            }
+
+            // An empty line repeats the last command, like gdb
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            match self.command(chip8, &line) {
+                Ok(Some(action)) => return action,
+                Ok(None) => (),
+                Err(message) => println!("{}", message),
+            }
+        }
+    }
+
+    /// Execute a single command, returning the action to take if it resumes execution
+    fn command<T: Drawable>(&mut self, chip8: &Chip8<T>, line: &str) -> Result<Option<Action>, String> {
+        let reg = chip8.registers();
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else { return Ok(None) };
+        let args: Vec<&str> = words.collect();
+
+        match (command, args.as_slice()) {
+            ("c" | "continue", []) => self.mode = Mode::Running,
+            ("s" | "step", []) => self.mode = Mode::Step(1),
+            ("s" | "step", [count]) => {
+                let count = parse_number(count)? as usize;
+                self.mode = Mode::Step(count.max(1));
+            }
+            ("n" | "next", []) => self.mode = Mode::StepOver(reg.sp),
+            ("finish" | "out", []) => {
+                if reg.sp == 0 {
+                    return Err("Not inside a subroutine".to_string());
+                }
+                self.mode = Mode::StepOut(reg.sp);
+            }
+            ("q" | "quit", []) => return Ok(Some(Action::Quit)),
+            ("b" | "break", [addr]) => {
+                let addr = parse_addr(addr)?;
+                let id = self.add_breakpoint(Breakpoint { addr: Some(addr), condition: None });
+                println!("Breakpoint {} at 0x{:03X}", id, addr);
+                return Ok(None);
+            }
+            ("b" | "break", ["if", condition @ ..]) => {
+                let condition = parse_condition(condition)?;
+                let id = self.add_breakpoint(Breakpoint { addr: None, condition: Some(condition) });
+                println!("Breakpoint {} when {}", id, condition);
+                return Ok(None);
+            }
+            ("b" | "break", [addr, "if", condition @ ..]) => {
+                let addr = parse_addr(addr)?;
+                let condition = parse_condition(condition)?;
+                let id = self.add_breakpoint(Breakpoint { addr: Some(addr), condition: Some(condition) });
+                println!("Breakpoint {} at 0x{:03X} when {}", id, addr, condition);
+                return Ok(None);
+            }
+            ("watch" | "rwatch" | "awatch", [addr, len @ ..]) if len.len() <= 1 => {
+                let start = parse_addr(addr)? as usize;
+                let len = match len {
+                    [len] => parse_number(len)? as usize,
+                    _ => 1,
+                };
+                if len == 0 {
+                    return Err("Length must be at least 1".to_string());
+                }
+                let watchpoint = Watchpoint {
+                    addrs: start..=start + len - 1,
+                    read: command != "watch",
+                    write: command != "rwatch",
+                };
+                let id = self.add_watchpoint(watchpoint);
+                println!("Watchpoint {} on 0x{:03X}-0x{:03X}", id, start, start + len - 1);
+                return Ok(None);
+            }
+            ("d" | "delete", []) => {
+                self.breakpoints.clear();
+                self.conditions_held.clear();
+                self.watchpoints.clear();
+                return Ok(None);
+            }
+            ("d" | "delete", [id]) => {
+                if !self.remove(parse_number(id)? as usize) {
+                    return Err(format!("No breakpoint or watchpoint {}", id));
+                }
+                return Ok(None);
+            }
+            ("i" | "info", []) => {
+                for (id, breakpoint) in self.breakpoints.iter() {
+                    print!("{:>3}  break", id);
+                    if let Some(addr) = breakpoint.addr {
+                        print!(" 0x{:03X}", addr);
+                    }
+                    if let Some(condition) = breakpoint.condition {
+                        print!(" if {}", condition);
+                    }
+                    println!();
+                }
+                for (id, watchpoint) in self.watchpoints.iter() {
+                    let kind = match (watchpoint.read, watchpoint.write) {
+                        (true, true) => "awatch",
+                        (true, false) => "rwatch",
+                        _ => "watch",
+                    };
+                    println!("{:>3}  {} 0x{:03X}-0x{:03X}", id, kind, watchpoint.addrs.start(), watchpoint.addrs.end());
+                }
+                return Ok(None);
+            }
+            ("r" | "regs", []) => {
+                print_registers(chip8);
+                return Ok(None);
+            }
+            ("stack", []) => {
+                print_stack(chip8);
+                return Ok(None);
+            }
+            ("l" | "list", []) => {
+                print_disassembly(chip8, reg.pc, 4, 6);
+                return Ok(None);
+            }
+            ("l" | "list", [addr]) => {
+                print_disassembly(chip8, parse_addr(addr)?, 0, 10);
+                return Ok(None);
+            }
+            ("x", [addr, len @ ..]) if len.len() <= 1 => {
+                let start = parse_addr(addr)? as usize;
+                let len = match len {
+                    [len] => parse_number(len)? as usize,
+                    _ => 16,
+                };
+                let memory = chip8.memory();
+                let end = (start + len).min(memory.len());
+                for (row, bytes) in memory[start..end].chunks(16).enumerate() {
+                    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    println!("0x{:03X}  {}", start + row * 16, hex.join(" "));
+                }
+                return Ok(None);
+            }
+            ("h" | "help", []) => {
+                println!("{}", HELP);
+                return Ok(None);
+            }
+            _ => return Err(format!("Invalid command '{}', type 'help' for the list of commands", line)),
+        }
+
+        Ok(Some(Action::Resume))
+    }
+}
+
+const HELP: &str = "\
+c, continue               resume execution
+s, step [count]           execute one or more instructions
+n, next                   execute one instruction, running through subroutine calls
+finish, out               run until the current subroutine returns
+b, break ADDR [if COND]   stop at ADDR, optionally only when COND holds (e.g. v3 == 5)
+b, break if COND          stop whenever COND starts holding
+watch ADDR [LEN]          stop before writes to memory
+rwatch ADDR [LEN]         stop before reads from memory
+awatch ADDR [LEN]         stop before reads from or writes to memory
+d, delete [ID]            delete a breakpoint or watchpoint, or all of them
+i, info                   list breakpoints and watchpoints
+r, regs                   print the registers
+stack                     print the call stack
+l, list [ADDR]            disassemble around pc or from ADDR
+x ADDR [LEN]              dump memory
+q, quit                   stop the emulator
+Pressing F12 in the emulator window stops execution";
+
+impl Condition {
+    pub fn holds<T: Drawable>(&self, chip8: &Chip8<T>) -> bool {
+        let reg = chip8.registers();
+        let value = match self.reg {
+            Register::V(x) => reg.v[x as usize & 0xF] as u16,
+            Register::I => reg.i,
+            Register::Pc => reg.pc,
+            Register::Sp => reg.sp as u16,
+            Register::Dt => reg.dt as u16,
+            Register::St => reg.st as u16,
+        };
+        match self.cmp {
+            Comparison::Eq => value == self.value,
+            Comparison::Ne => value != self.value,
+            Comparison::Lt => value < self.value,
+            Comparison::Gt => value > self.value,
+            Comparison::Le => value <= self.value,
+            Comparison::Ge => value >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reg {
+            Register::V(x) => write!(f, "v{:X}", x)?,
+            Register::I => write!(f, "i")?,
+            Register::Pc => write!(f, "pc")?,
+            Register::Sp => write!(f, "sp")?,
+            Register::Dt => write!(f, "dt")?,
+            Register::St => write!(f, "st")?,
+        }
+        let cmp = match self.cmp {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Gt => ">",
+            Comparison::Le => "<=",
+            Comparison::Ge => ">=",
+        };
+        write!(f, " {} 0x{:X}", cmp, self.value)
+    }
+}
+
+fn print_registers<T: Drawable>(chip8: &Chip8<T>) {
+    let reg = chip8.registers();
+    for (offset, values) in reg.v.chunks(8).enumerate() {
+        let regs: Vec<String> = values.iter().enumerate().map(|(x, val)| format!("V{:X}={:02X}", offset * 8 + x, val)).collect();
+        println!("{}", regs.join(" "));
+    }
+    println!("I={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}", reg.i, reg.pc, reg.sp, reg.dt, reg.st);
+}
+
+fn print_stack<T: Drawable>(chip8: &Chip8<T>) {
+    let reg = chip8.registers();
+    if reg.sp == 0 {
+        println!("Stack is empty");
+    }
+    for (level, addr) in chip8.stack()[..reg.sp as usize].iter().enumerate().rev() {
+        println!("#{:<2} return to 0x{:03X}", level, addr);
+    }
+}
+
+/// Disassemble `after` instructions from `addr`, preceded by `before` instructions.
+/// Instructions have no fixed alignment, so the ones before `addr` are a best guess
+fn print_disassembly<T: Drawable>(chip8: &Chip8<T>, addr: u16, before: u16, after: u16) {
+    let memory = chip8.memory();
+    let pc = chip8.registers().pc;
+    let mut addr = addr.saturating_sub(before * 2) as usize;
+
+    for _ in 0..before + after {
+        if addr + 1 >= memory.len() {
+            break;
+        }
+        let opcode = u16::from_be_bytes([memory[addr], memory[addr + 1]]);
+        let marker = if addr == pc as usize { "=>" } else { "  " };
+        let (text, len) = disasm::disassemble_at(memory, addr).unwrap_or(("???".to_string(), 2));
+        println!("{} 0x{:03X}  {:04X}  {}", marker, addr, opcode, text);
+        addr += len;
+    }
+}
+
+fn parse_number(text: &str) -> Result<u32, String> {
+    let lower = text.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        lower.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number '{}'", text))
+}
+
+fn parse_addr(text: &str) -> Result<u16, String> {
+    let addr = parse_number(text)?;
+    u16::try_from(addr).map_err(|_| format!("Address {} out of range", text))
+}
+
+fn parse_condition(words: &[&str]) -> Result<Condition, String> {
+    let [reg, cmp, value] = words else {
+        return Err("Conditions are written as REGISTER OPERATOR VALUE, e.g. v3 == 5".to_string());
+    };
+
+    let reg = match reg.to_ascii_lowercase().as_str() {
+        "i" => Register::I,
+        "pc" => Register::Pc,
+        "sp" => Register::Sp,
+        "dt" => Register::Dt,
+        "st" => Register::St,
+        name => match name.strip_prefix('v').and_then(|x| u8::from_str_radix(x, 16).ok()) {
+            Some(x) if x < 16 => Register::V(x),
+            _ => return Err(format!("Unknown register '{}'", reg)),
+        },
+    };
+    let cmp = match *cmp {
+        "==" => Comparison::Eq,
+        "!=" => Comparison::Ne,
+        "<" => Comparison::Lt,
+        ">" => Comparison::Gt,
+        "<=" => Comparison::Le,
+        ">=" => Comparison::Ge,
+        _ => return Err(format!("Unknown operator '{}'", cmp)),
+    };
+    let value = parse_addr(value)?;
+
+    Ok(Condition { reg, cmp, value })
+}
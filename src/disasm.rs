@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::instructions::Inst;
+
+/// Produce an annotated listing of `rom`, loaded at `origin`.
+///
+/// Code is told apart from data by following every path of execution from the entry point, so bytes
+/// that are never reached (sprites, tables, ...) are listed as data. Targets of jumps and calls get a label,
+/// unless they are outside of the ROM or in the middle of an instruction
+pub fn disassemble(rom: &[u8], origin: u16) -> String {
+    listing(rom, origin, true)
+}
+
+/// Produce the same listing as `disassemble` without the address and raw byte columns, as source that
+/// `asm::assemble` turns back into `rom`
+pub fn disassemble_source(rom: &[u8], origin: u16) -> String {
+    listing(rom, origin, false)
+}
+
+fn listing(rom: &[u8], origin: u16, columns: bool) -> String {
+    let (code, labels) = trace(rom, origin);
+
+    let mut out = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = origin as usize + offset;
+        if let Some(label) = labels.get(&addr) {
+            writeln!(out, "{}:", label).unwrap();
+        }
+
+        let len = code[offset];
+        if len > 0 {
+            let opcode = read_word(rom, offset).unwrap();
+            let inst = Inst::decode(opcode).unwrap();
+            let raw = rom[offset..offset + len].iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ");
+            let text = match inst {
+                Inst::JP(target) if labels.contains_key(&(target.value() as usize)) => {
+                    format!("JP {}", labels[&(target.value() as usize)])
+                }
+                Inst::CALL(target) if labels.contains_key(&(target.value() as usize)) => {
+                    format!("CALL {}", labels[&(target.value() as usize)])
+                }
+                Inst::LDIL => format!("{} 0x{:04X}", inst, read_word(rom, offset + 2).unwrap()),
+                _ => inst.to_string(),
+            };
+            if columns {
+                writeln!(out, "    0x{:03X}  {:<11}  {}", addr, raw, text).unwrap();
+            } else {
+                writeln!(out, "    {}", text).unwrap();
+            }
+            offset += len;
+        } else {
+            let byte = rom[offset];
+            let pixels: String = (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect();
+            if columns {
+                writeln!(out, "    0x{:03X}  {:02X}           db 0x{:02X}  ; {}", addr, byte, byte, pixels).unwrap();
+            } else {
+                writeln!(out, "    db 0x{:02X}  ; {}", byte, pixels).unwrap();
+            }
+            offset += 1;
+        }
+    }
+
+    out
+}
+
+/// Disassemble the single instruction at `addr` in `memory`, returning its text and length in bytes.
+/// Returns None if there is no valid instruction there
+pub fn disassemble_at(memory: &[u8], addr: usize) -> Option<(String, usize)> {
+    let inst = Inst::decode(read_word(memory, addr)?)?;
+    let text = match inst {
+        Inst::LDIL => format!("{} 0x{:04X}", inst, read_word(memory, addr + 2)?),
+        _ => inst.to_string(),
+    };
+    Some((text, inst_len(&inst)))
+}
+
+/// Follow all the reachable instructions from `origin`, returning the length of the instruction starting at
+/// each byte, 0 for data, and the labels of the jump and call targets
+fn trace(rom: &[u8], origin: u16) -> (Vec<usize>, BTreeMap<usize, String>) {
+    let origin = origin as usize;
+    let mut code = vec![0; rom.len()];
+    let mut labels = BTreeMap::new();
+    let mut pending = vec![origin];
+
+    while let Some(addr) = pending.pop() {
+        let Some(offset) = addr.checked_sub(origin) else { continue };
+        if offset >= rom.len() || code[offset] > 0 {
+            continue;
+        }
+        let Some(opcode) = read_word(rom, offset) else { continue };
+        let Some(inst) = Inst::decode(opcode) else { continue };
+        if inst == Inst::LDIL && read_word(rom, offset + 2).is_none() {
+            continue;
+        }
+
+        code[offset] = inst_len(&inst);
+        let next = addr + code[offset];
+
+        match inst {
+            // Hires programs for the COSMAC VIP skip the interpreter bundled with them, as the emulator does
+            Inst::JP(_) if offset == 0 && opcode == 0x1260 => {
+                labels.insert(0x2C0, "loc_2C0".to_string());
+                pending.push(0x2C0);
+            }
+            Inst::JP(target) => {
+                let target = target.value() as usize;
+                labels.entry(target).or_insert_with(|| format!("loc_{:03X}", target));
+                pending.push(target);
+            }
+            Inst::CALL(target) => {
+                let target = target.value() as usize;
+                labels.insert(target, format!("sub_{:03X}", target));
+                pending.push(target);
+                pending.push(next);
+            }
+            Inst::RET | Inst::EXIT | Inst::JPV(_) => {}
+            Inst::SE(..) | Inst::SNE(..) | Inst::SEV(..) | Inst::SNEV(..) | Inst::SKP(_) | Inst::SKNP(_) => {
+                // Either the next instruction runs or it is skipped, taking into account F000 NNNN is 4 bytes long
+                let skipped = match read_word(rom, next - origin) {
+                    Some(0xF000) => next + 4,
+                    _ => next + 2,
+                };
+                pending.push(next);
+                pending.push(skipped);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    // Labels can only be put on the lines of the listing, jumps anywhere else show the raw address
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        lines.push(origin + offset);
+        offset += code[offset].max(1);
+    }
+    labels.retain(|addr, _| lines.binary_search(addr).is_ok());
+
+    (code, labels)
+}
+
+fn inst_len(inst: &Inst) -> usize {
+    match inst {
+        Inst::LDIL => 4,
+        _ => 2,
+    }
+}
+
+fn read_word(rom: &[u8], offset: usize) -> Option<u16> {
+    let bytes = rom.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
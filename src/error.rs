@@ -0,0 +1,117 @@
+use std::fmt;
+
+/// Fault raised by the interpreter while executing a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The fetched opcode does not map to any known instruction
+    InvalidOpcode { pc: u16, opcode: u16 },
+
+    /// CALL with all 16 stack entries already in use
+    StackOverflow { pc: u16, opcode: u16 },
+
+    /// RET with an empty stack
+    StackUnderflow { pc: u16, opcode: u16 },
+
+    /// The instruction tried to access memory past the end of the address space
+    MemoryOutOfBounds { pc: u16, opcode: u16, addr: usize },
+
+    /// The program counter points outside of memory, so no opcode could be fetched
+    PcOutOfBounds { pc: u16 },
+
+    /// The ROM doesn't fit in memory from 0x200
+    RomTooLarge { len: usize, max: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Chip8Error::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode {:04X} at {:03X}", opcode, pc)
+            }
+            Chip8Error::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow executing {:04X} at {:03X}", opcode, pc)
+            }
+            Chip8Error::StackUnderflow { pc, opcode } => {
+                write!(f, "stack underflow executing {:04X} at {:03X}", opcode, pc)
+            }
+            Chip8Error::MemoryOutOfBounds { pc, opcode, addr } => {
+                write!(f, "memory access out of bounds ({:X}) executing {:04X} at {:03X}", addr, opcode, pc)
+            }
+            Chip8Error::PcOutOfBounds { pc } => {
+                write!(f, "program counter out of bounds ({:X})", pc)
+            }
+            Chip8Error::RomTooLarge { len, max } => {
+                write!(f, "ROM too large ({} bytes, at most {})", len, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+/// Fault raised by a single instruction, before the PC and opcode are attached to it
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Fault {
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds(usize),
+}
+
+impl Fault {
+    pub(crate) fn at(self, pc: u16, opcode: u16) -> Chip8Error {
+        match self {
+            Fault::StackOverflow => Chip8Error::StackOverflow { pc, opcode },
+            Fault::StackUnderflow => Chip8Error::StackUnderflow { pc, opcode },
+            Fault::MemoryOutOfBounds(addr) => Chip8Error::MemoryOutOfBounds { pc, opcode, addr },
+        }
+    }
+}
+
+/// Reason a save state could not be restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The data is not a save state
+    BadMagic,
+
+    /// The save state was written by an incompatible version of the format
+    UnsupportedVersion(u16),
+
+    /// The save state was taken while running a different ROM
+    RomMismatch,
+
+    /// The data ends before the save state is complete
+    Truncated,
+
+    /// The save state contains invalid values
+    Corrupted,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            StateError::RomMismatch => write!(f, "save state belongs to a different ROM"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Corrupted => write!(f, "save state is corrupted"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Error found while assembling a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// Line of the source where the error was found, starting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
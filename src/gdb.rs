@@ -0,0 +1,431 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+use crate::graphics::Drawable;
+use crate::scheduler::{self, FrameBudget, Scheduler};
+
+/// Description of the registers sent to GDB, in the order used by the `g` and `p` packets.
+/// There is no CHIP-8 architecture in GDB, front ends are expected to rely on this description
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+// Index of the registers after V0-VF
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+const REG_COUNT: usize = 21;
+
+// Signals reported to GDB when execution stops
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// Wait for GDB to connect on `addr` and let it control `chip8` through the remote serial protocol,
+/// until it detaches or the connection is closed.
+///
+/// Registers are V0-VF, I, PC, SP, DT and ST, with the 16-bit ones in big endian order. While the
+/// program runs, instructions are executed at the frequency of `chip8` and the timers and the screen
+/// are updated once per frame, 60 times per second
+pub fn serve<T: Drawable>(chip8: &mut Chip8<T>, addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("Waiting for GDB to connect on {}", listener.local_addr()?);
+    serve_on(chip8, &listener)
+}
+
+/// Like `serve`, with a listener bound by the caller, e.g. to a port picked by the system
+pub fn serve_on<T: Drawable>(chip8: &mut Chip8<T>, listener: &TcpListener) -> io::Result<()> {
+    let (stream, peer) = listener.accept()?;
+    eprintln!("GDB connected from {}", peer);
+    stream.set_nodelay(true)?;
+
+    chip8.graphics_mut().init();
+    let mut stub = GdbStub {
+        scheduler: Scheduler::new(chip8.freq()),
+        chip8,
+        stream,
+        received: VecDeque::new(),
+        breakpoints: BTreeSet::new(),
+        no_ack: false,
+        frame: None,
+    };
+    let result = stub.session();
+    stub.chip8.graphics_mut().finalize();
+    result
+}
+
+struct GdbStub<'a, T: Drawable> {
+    chip8: &'a mut Chip8<T>,
+    stream: TcpStream,
+    /// Bytes received while checking for interrupts, to be read before the stream
+    received: VecDeque<u8>,
+    breakpoints: BTreeSet<u16>,
+    no_ack: bool,
+    scheduler: Scheduler,
+    /// Frame interrupted by the last stop, which is finished when resuming
+    frame: Option<FrameBudget>,
+}
+
+impl<'a, T: Drawable> GdbStub<'a, T> {
+    fn session(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match packet.as_str() {
+                // Detaching or killing ends the session, either way the emulator stops
+                "D" => {
+                    self.write_packet("OK")?;
+                    return Ok(());
+                }
+                "k" => return Ok(()),
+                _ => self.handle(&packet)?,
+            };
+            self.write_packet(&reply)?;
+        }
+        Ok(())
+    }
+
+    /// Reply to a packet, an empty reply tells GDB the packet is not supported
+    fn handle(&mut self, packet: &str) -> io::Result<String> {
+        if packet.is_empty() {
+            return Ok(String::new());
+        }
+        let (command, args) = packet.split_at(1);
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..REG_COUNT).map(|reg| self.read_register(reg)).collect(),
+            "G" => {
+                // Every register is decoded before writing any, so a short packet changes nothing
+                let mut bytes = decode_hex(args).unwrap_or_default().into_iter();
+                let values: Option<Vec<u16>> = (0..REG_COUNT)
+                    .map(|reg| match register_size(reg) {
+                        1 => bytes.next().map(u16::from),
+                        _ => bytes.next().zip(bytes.next()).map(|(high, low)| u16::from_be_bytes([high, low])),
+                    })
+                    .collect();
+                match values {
+                    Some(values) => {
+                        for (reg, value) in values.into_iter().enumerate() {
+                            self.write_register(reg, value);
+                        }
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(reg) if reg < REG_COUNT => self.read_register(reg),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(reg, value)| {
+                    let reg = usize::from_str_radix(reg, 16).ok().filter(|&reg| reg < REG_COUNT)?;
+                    let bytes = decode_hex(value)?;
+                    let value = bytes.iter().fold(0, |value, &byte| value << 8 | byte as u16);
+                    Some((reg, value))
+                });
+                match parsed {
+                    Some((reg, value)) => {
+                        self.write_register(reg, value);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "m" => match parse_range(args).and_then(|range| self.chip8.memory().get(range)) {
+                Some(bytes) => encode_hex(bytes),
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| Some((parse_range(range)?, decode_hex(data)?)));
+                match write {
+                    Some((range, data)) if range.len() == data.len() => match self.chip8.memory_mut().get_mut(range) {
+                        Some(memory) => {
+                            memory.copy_from_slice(&data);
+                            "OK".to_string()
+                        }
+                        None => "E01".to_string(),
+                    },
+                    _ => "E01".to_string(),
+                }
+            }
+            // Software and hardware breakpoints are the same thing for an emulator
+            "Z" | "z" => match parse_breakpoint(args) {
+                Some(addr) => {
+                    if command == "Z" {
+                        self.breakpoints.insert(addr);
+                    } else {
+                        self.breakpoints.remove(&addr);
+                    }
+                    "OK".to_string()
+                }
+                None => String::new(),
+            },
+            "s" | "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.chip8.registers_mut().pc = addr as u16;
+                }
+                self.resume(command == "s")?
+            }
+            "H" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+".to_string();
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, len)) = args.split_once(',') else { return "E01".to_string() };
+            let (Some(offset), Some(len)) = (parse_hex(offset), parse_hex(len)) else { return "E01".to_string() };
+            let offset = offset.min(TARGET_XML.len());
+            let end = (offset + len).min(TARGET_XML.len());
+            let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+            return format!("{}{}", prefix, escape_binary(&TARGET_XML[offset..end]));
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Execute instructions until a breakpoint is hit, GDB interrupts or after a single instruction
+    /// when stepping, returning the stop reply
+    fn resume(&mut self, single_step: bool) -> io::Result<String> {
+        // Time spent stopped is not time the emulator fell behind
+        self.scheduler.reset();
+        loop {
+            if self.chip8.is_halted() || self.chip8.graphics().should_close() {
+                return Ok("W00".to_string());
+            }
+
+            let frame = self.frame.get_or_insert_with(|| self.scheduler.begin_frame());
+            if !frame.has_cycles() {
+                let executed = frame.executed;
+                self.frame = None;
+                self.chip8.finish_frame();
+                let next_frame = self.scheduler.end_frame(executed, true);
+                if self.interrupted()? {
+                    return Ok(format!("S{:02x}", SIGINT));
+                }
+                scheduler::sleep_until(next_frame);
+                continue;
+            }
+
+            frame.executed += 1;
+            if let Err(err) = self.chip8.step() {
+                eprintln!("{}", err);
+                let signal = match err {
+                    Chip8Error::InvalidOpcode { .. } => SIGILL,
+                    _ => SIGSEGV,
+                };
+                return Ok(format!("S{:02x}", signal));
+            }
+
+            if single_step {
+                return Ok(format!("S{:02x}", SIGTRAP));
+            }
+            // A waiting instruction is executed again from the same address, which doesn't hit its breakpoint
+            if !self.chip8.is_retrying() && self.breakpoints.contains(&self.chip8.registers().pc) {
+                return Ok(format!("T{:02x}swbreak:;", SIGTRAP));
+            }
+        }
+    }
+
+    /// Check, without blocking, whether GDB sent an interrupt request. Anything else it sent is kept for
+    /// `read_packet`
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut interrupted = false;
+        let mut byte = [0];
+        let result = loop {
+            match self.stream.read(&mut byte) {
+                Ok(1) if byte[0] == 0x03 => interrupted = true,
+                Ok(1) => self.received.push_back(byte[0]),
+                // Disconnections are noticed by `read_packet`
+                Ok(_) => break Ok(interrupted),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(interrupted),
+                Err(err) => break Err(err),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    fn read_register(&self, reg: usize) -> String {
+        let regs = self.chip8.registers();
+        match reg {
+            0..=15 => format!("{:02x}", regs.v[reg]),
+            REG_I => format!("{:04x}", regs.i),
+            REG_PC => format!("{:04x}", regs.pc),
+            REG_SP => format!("{:02x}", regs.sp),
+            REG_DT => format!("{:02x}", regs.dt),
+            REG_ST => format!("{:02x}", regs.st),
+            _ => unreachable!("invalid register {}", reg),
+        }
+    }
+
+    fn write_register(&mut self, reg: usize, value: u16) {
+        let regs = self.chip8.registers_mut();
+        match reg {
+            0..=15 => regs.v[reg] = value as u8,
+            REG_I => regs.i = value,
+            REG_PC => regs.pc = value,
+            // The stack only has 16 levels
+            REG_SP => regs.sp = (value as u8).min(16),
+            REG_DT => regs.dt = value as u8,
+            REG_ST => regs.st = value as u8,
+            _ => unreachable!("invalid register {}", reg),
+        }
+    }
+
+    /// Read the next packet, skipping acknowledgments and stray interrupts. Returns None when GDB disconnects
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let Some(byte) = self.read_byte()? else { return Ok(None) };
+            if byte != b'$' {
+                continue;
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let (Some(high), Some(low)) = (self.read_byte()?, self.read_byte()?) else { return Ok(None) };
+            let checksum = std::str::from_utf8(&[high, low]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+            if !self.no_ack {
+                if checksum != Some(checksum_of(&data)) {
+                    self.stream.write_all(b"-")?;
+                    continue;
+                }
+                self.stream.write_all(b"+")?;
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.received.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0];
+        match self.stream.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(err) if err.kind() == io::ErrorKind::ConnectionReset => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+}
+
+fn register_size(reg: usize) -> usize {
+    match reg {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// Parse `addr,length` into a memory range
+fn parse_range(text: &str) -> Option<std::ops::Range<usize>> {
+    let (addr, len) = text.split_once(',')?;
+    let addr = parse_hex(addr)?;
+    Some(addr..addr.checked_add(parse_hex(len)?)?)
+}
+
+/// Parse the `type,addr,kind` arguments of a breakpoint packet, only breakpoints are supported and not
+/// watchpoints
+fn parse_breakpoint(text: &str) -> Option<u16> {
+    let mut args = text.split(',');
+    let kind = args.next()?;
+    if kind != "0" && kind != "1" {
+        return None;
+    }
+    u16::try_from(parse_hex(args.next()?)?).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|pos| u8::from_str_radix(text.get(pos..pos + 2)?, 16).ok())
+        .collect()
+}
+
+/// Escape the characters with a special meaning in binary replies
+fn escape_binary(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '#' | '$' | '}' | '*') {
+            out.push('}');
+            out.push((c as u8 ^ 0x20) as char);
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
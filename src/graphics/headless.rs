@@ -0,0 +1,169 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use super::{Display, Keypad, Beeper, Command};
+
+/// Frontend without any window or audio device, for automated testing.
+///
+/// The screen is kept in memory, key presses are scripted ahead of time and sound is recorded as a list
+/// of on/off transitions. Time is measured in ticks, one for every call to `update`, which happens once
+/// per frame when the emulator is driven through `Chip8::run_frame`
+pub struct HeadlessGraphics {
+    width: usize,
+    height: usize,
+    framebuffer: Vec<Vec<u8>>,
+    ticks: u64,
+    keys: [bool; 16],
+    script: VecDeque<(u64, u8, bool)>,
+    commands: VecDeque<Command>,
+    close_requested: bool,
+    sound_on: Cell<bool>,
+    sound_events: RefCell<Vec<(u64, bool)>>,
+    audio_pattern: ([u8; 16], u8),
+    status: String,
+}
+
+impl HeadlessGraphics {
+    pub fn new(width: usize, height: usize) -> HeadlessGraphics {
+        HeadlessGraphics {
+            width,
+            height,
+            framebuffer: vec![vec![0; width]; height],
+            ticks: 0,
+            keys: [false; 16],
+            script: VecDeque::new(),
+            commands: VecDeque::new(),
+            close_requested: false,
+            sound_on: Cell::new(false),
+            sound_events: RefCell::new(Vec::new()),
+            audio_pattern: ([0; 16], 0),
+            status: String::new(),
+        }
+    }
+
+    /// Press or release `key` when the given tick is reached
+    pub fn script_key(&mut self, tick: u64, key: u8, pressed: bool) {
+        let pos = self.script.partition_point(|&(t, _, _)| t <= tick);
+        self.script.insert(pos, (tick, key, pressed));
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.keys[key as usize & 0xF] = true;
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        self.keys[key as usize & 0xF] = false;
+    }
+
+    /// Queue a command for the run loop
+    pub fn push_command(&mut self, command: Command) {
+        self.commands.push_back(command);
+    }
+
+    pub fn request_close(&mut self) {
+        self.close_requested = true;
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Last screen presented through `draw_screen`
+    pub fn framebuffer(&self) -> &[Vec<u8>] {
+        &self.framebuffer
+    }
+
+    pub fn is_sound_on(&self) -> bool {
+        self.sound_on.get()
+    }
+
+    /// Sound transitions as (tick, on) pairs
+    pub fn sound_events(&self) -> Vec<(u64, bool)> {
+        self.sound_events.borrow().clone()
+    }
+
+    /// Last XO-CHIP audio pattern and pitch set by the program
+    pub fn audio_pattern(&self) -> &([u8; 16], u8) {
+        &self.audio_pattern
+    }
+
+    /// Last status shown through `show_status`
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    fn apply_script(&mut self) {
+        while let Some(&(tick, key, pressed)) = self.script.front() {
+            if tick > self.ticks {
+                break;
+            }
+            self.keys[key as usize & 0xF] = pressed;
+            self.script.pop_front();
+        }
+    }
+
+    fn set_sound(&self, on: bool) {
+        if self.sound_on.replace(on) != on {
+            self.sound_events.borrow_mut().push((self.ticks, on));
+        }
+    }
+}
+
+impl Display for HeadlessGraphics {
+    fn init(&mut self) {
+        self.apply_script();
+    }
+
+    fn finalize(&mut self) {
+        self.set_sound(false);
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn draw_screen(&mut self, vram: &Vec<Vec<u8>>) {
+        self.framebuffer.clone_from(vram);
+    }
+
+    fn show_status(&mut self, status: &str) {
+        self.status = status.to_string();
+    }
+}
+
+impl Keypad for HeadlessGraphics {
+    fn update(&mut self, _timeout_millis: u32) {
+        self.ticks += 1;
+        self.apply_script();
+    }
+
+    fn is_key_pressed(&self, key: u8) -> bool {
+        self.keys[key as usize & 0xF]
+    }
+
+    fn should_close(&self) -> bool {
+        self.close_requested
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+}
+
+impl Beeper for HeadlessGraphics {
+    fn sound_resume(&self) {
+        self.set_sound(true);
+    }
+
+    fn sound_pause(&self) {
+        self.set_sound(false);
+    }
+
+    fn sound_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.audio_pattern = (*pattern, pitch);
+    }
+}
@@ -1,24 +1,154 @@
+mod headless;
+#[cfg(feature = "sdl")]
 mod sdl;
+#[cfg(feature = "terminal")]
+mod terminal;
+#[cfg(feature = "terminal")]
+mod terminal_keypad;
+#[cfg(feature = "terminal")]
+mod tui;
 
-pub use self::sdl::SDLGraphics;
+pub use self::headless::HeadlessGraphics;
 
-pub trait Drawable {
+#[cfg(feature = "sdl")]
+pub use self::sdl::{SDLGraphics, SdlDisplay, SdlKeypad, SdlBeeper};
+
+#[cfg(feature = "terminal")]
+pub use self::terminal::TerminalGraphics;
+#[cfg(feature = "terminal")]
+pub use self::tui::{TuiGraphics, run_tui};
+
+/// Request from the user to the emulator, outside of the emulated keypad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    SaveState(u8),
+    LoadState(u8),
+
+    /// Start or stop playing the game backwards
+    Rewind(bool),
+
+    /// Stop in the debugger, if there is one
+    Break,
+
+    /// Pause or resume the emulation
+    Pause,
+
+    /// Run a single frame while paused
+    FrameAdvance,
+
+    /// Start or stop running faster, while a key is held
+    Turbo(bool),
+
+    /// Toggle running at a fraction of the speed
+    SlowMotion,
+
+    /// Execute one more instruction per frame
+    IncreaseSpeed,
+
+    /// Execute one less instruction per frame
+    DecreaseSpeed,
+}
+
+/// Output of the emulated screen, and lifecycle of the frontend as a whole
+pub trait Display {
     fn init(&mut self);
     fn finalize(&mut self);
     fn width(&self) -> usize;
     fn height(&self) -> usize;
-    fn update(&mut self, timeout_millis: u32);
-
-
-    // Graphics
     fn draw_screen(&mut self, vram: &Vec<Vec<u8>>);
 
-    // Input
+    /// Show a short status line next to the screen, like the emulation speed. Ignored by default
+    fn show_status(&mut self, _status: &str) {}
+}
+
+/// Source of the emulated keypad state, and of the user requests outside of it
+pub trait Keypad {
+    /// Process pending input, waiting up to `timeout_millis` for some to arrive
+    fn update(&mut self, timeout_millis: u32);
     fn is_key_pressed(&self, key: u8) -> bool;
-    fn wait_for_key(&mut self) -> u8;
+
+    /// Whether the user asked to quit, e.g. by closing the window
     fn should_close(&self) -> bool;
+    fn poll_command(&mut self) -> Option<Command>;
+}
 
-    // Sound
+/// Output of the emulated sound
+pub trait Beeper {
     fn sound_resume(&self);
     fn sound_pause(&self);
+
+    /// Set the XO-CHIP audio pattern, a 1-bit 128 samples waveform played at the rate given by `pitch`
+    fn sound_pattern(&mut self, pattern: &[u8; 16], pitch: u8);
+}
+
+/// Everything the emulator needs from its frontend, implemented by any type that is a display, a keypad and
+/// a beeper at once. Separate implementations can be combined with `Frontend`
+pub trait Drawable: Display + Keypad + Beeper {}
+
+impl<T: Display + Keypad + Beeper> Drawable for T {}
+
+/// Frontend assembled from separate display, keypad and beeper implementations, for example to play the sound
+/// of an SDL window through another audio sink
+pub struct Frontend<D: Display, K: Keypad, B: Beeper> {
+    pub display: D,
+    pub keypad: K,
+    pub beeper: B,
+}
+
+impl<D: Display, K: Keypad, B: Beeper> Display for Frontend<D, K, B> {
+    fn init(&mut self) {
+        self.display.init();
+    }
+
+    fn finalize(&mut self) {
+        self.display.finalize();
+    }
+
+    fn width(&self) -> usize {
+        self.display.width()
+    }
+
+    fn height(&self) -> usize {
+        self.display.height()
+    }
+
+    fn draw_screen(&mut self, vram: &Vec<Vec<u8>>) {
+        self.display.draw_screen(vram);
+    }
+
+    fn show_status(&mut self, status: &str) {
+        self.display.show_status(status);
+    }
+}
+
+impl<D: Display, K: Keypad, B: Beeper> Keypad for Frontend<D, K, B> {
+    fn update(&mut self, timeout_millis: u32) {
+        self.keypad.update(timeout_millis);
+    }
+
+    fn is_key_pressed(&self, key: u8) -> bool {
+        self.keypad.is_key_pressed(key)
+    }
+
+    fn should_close(&self) -> bool {
+        self.keypad.should_close()
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        self.keypad.poll_command()
+    }
+}
+
+impl<D: Display, K: Keypad, B: Beeper> Beeper for Frontend<D, K, B> {
+    fn sound_resume(&self) {
+        self.beeper.sound_resume();
+    }
+
+    fn sound_pause(&self) {
+        self.beeper.sound_pause();
+    }
+
+    fn sound_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.beeper.sound_pattern(pattern, pitch);
+    }
 }
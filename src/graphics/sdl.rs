@@ -1,25 +1,35 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
-use sdl2::{pixels::Color, event::Event, keyboard::Scancode, video::Window, render::Canvas, EventPump, rect::Rect, audio::{AudioCallback, AudioSpecDesired, AudioDevice}};
+use sdl2::{pixels::Color, event::Event, keyboard::{Scancode, Keycode, Mod}, video::Window, render::Canvas, EventPump, rect::Rect, audio::{AudioCallback, AudioSpecDesired, AudioDevice}, Sdl};
 
-use super::Drawable;
+use super::{Display, Keypad, Beeper, Frontend, Command};
 
 extern crate sdl2;
 
-pub struct SDLGraphics {
+/// SDL window with its keyboard and audio output
+pub type SDLGraphics = Frontend<SdlDisplay, SdlKeypad, SdlBeeper>;
+
+impl SDLGraphics {
+    pub fn new(width_cells: u32, height_cells: u32, pixel_size: u32, keymap: HashMap<u8, char>) -> SDLGraphics {
+        let ctx = sdl2::init().unwrap();
+        Frontend {
+            display: SdlDisplay::new(&ctx, width_cells, height_cells, pixel_size),
+            keypad: SdlKeypad::new(&ctx, keymap),
+            beeper: SdlBeeper::new(&ctx),
+        }
+    }
+}
+
+/// Window showing the screen, with every game pixel drawn as a `pixel_size` square
+pub struct SdlDisplay {
     width_cells: u32,
     height_cells: u32,
     pixel_size: u32,
     canvas: Canvas<Window>,
-    event_pump: EventPump,
-    keymap: HashMap<u8, char>,
-    close_requested: bool,
-    audio_device: AudioDevice<SquareWave>,
 }
 
-impl SDLGraphics {
-    pub fn new(width_cells: u32, height_cells: u32, pixel_size: u32, keymap: HashMap<u8, char>) -> SDLGraphics {
-        let ctx = sdl2::init().unwrap();
+impl SdlDisplay {
+    pub fn new(ctx: &Sdl, width_cells: u32, height_cells: u32, pixel_size: u32) -> SdlDisplay {
         let video = ctx.video().unwrap();
         let window = video.window("Chip8", width_cells * pixel_size, height_cells * pixel_size)
             .position_centered()
@@ -28,36 +38,17 @@ impl SDLGraphics {
         let canvas = window.into_canvas()
             .build()
             .unwrap();
-        let event_pump = ctx.event_pump().unwrap();
-        let audio = ctx.audio().unwrap();
-        let audio_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),
-            samples: None
-        };
 
-        let audio_device = audio.open_playback(None, &audio_spec, |spec| {
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25
-            }
-        }).unwrap();
-
-        SDLGraphics {
+        SdlDisplay {
             width_cells,
             height_cells,
             pixel_size,
             canvas,
-            event_pump,
-            keymap,
-            close_requested: false,
-            audio_device,
         }
     }
 }
 
-impl Drawable for SDLGraphics {
+impl Display for SdlDisplay {
     fn init(&mut self) {
         // Clear the screen
         self.canvas.set_draw_color(Color::RGB(0, 0, 0));
@@ -77,72 +68,162 @@ impl Drawable for SDLGraphics {
         self.height_cells as usize
     }
 
-    fn is_key_pressed(&self, key: u8) -> bool {
-        let &keychar = self.keymap.get(&key).unwrap();
-        self.event_pump.keyboard_state().is_scancode_pressed(keychar_to_scancode(keychar))
-    }
-    
-    fn should_close(&self) -> bool {
-        self.close_requested
-    }
-
-    fn wait_for_key(&mut self) -> u8 {
-        loop {
-            let event = self.event_pump.wait_event();
+    fn draw_screen(&mut self, vram: &Vec<Vec<u8>>) {
+        // The window size is fixed, so the screen is scaled to fit it in any display mode
+        let pixel_size = (self.pixel_size * self.width_cells / vram[0].len() as u32)
+            .min(self.pixel_size * self.height_cells / vram.len() as u32);
 
-            match event {
-                Event::KeyDown { keycode: Some(keycode), .. } => {
-                    for (key, keychar) in self.keymap.iter() {
-                        if keycode.name().to_ascii_lowercase() == String::from(*keychar).to_ascii_lowercase() {
-                            return *key;
-                        }
-                    }
-                },
-                Event::Quit { .. } => {
-                    self.close_requested = true;
-                    return 42
-                }
-                _ => (),
-            }
-        }
-    }
+        self.canvas.set_draw_color(PALETTE[0]);
+        self.canvas.clear();
 
-    fn draw_screen(&mut self, vram: &Vec<Vec<u8>>) {
         for (y, row) in vram.iter().enumerate() {
             for (x, &pixel) in row.iter().enumerate() {
-                if pixel != 0 {
-                    self.canvas.set_draw_color(Color::RGB(255, 255, 255));
-                } else {
-                    self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-                }
+                self.canvas.set_draw_color(PALETTE[pixel as usize & 0b11]);
 
                 self.canvas.fill_rect(Rect::new(
-                    (x * self.pixel_size as usize) as i32,
-                    (y * self.pixel_size as usize) as i32,
-                    self.pixel_size,
-                    self.pixel_size
+                    (x * pixel_size as usize) as i32,
+                    (y * pixel_size as usize) as i32,
+                    pixel_size,
+                    pixel_size
                 )).expect("Failed to draw rectangle, possible driver failure");
             }
         }
         self.canvas.present();
     }
 
+    fn show_status(&mut self, status: &str) {
+        let title = if status.is_empty() { "Chip8".to_string() } else { format!("Chip8 - {}", status) };
+        self.canvas.window_mut().set_title(&title).expect("Failed to set the window title");
+    }
+}
+
+/// Keyboard and window events of SDL, which only arrive once a window is open
+pub struct SdlKeypad {
+    event_pump: EventPump,
+    keymap: HashMap<u8, char>,
+    close_requested: bool,
+    commands: VecDeque<Command>,
+}
+
+impl SdlKeypad {
+    pub fn new(ctx: &Sdl, keymap: HashMap<u8, char>) -> SdlKeypad {
+        SdlKeypad {
+            event_pump: ctx.event_pump().unwrap(),
+            keymap,
+            close_requested: false,
+            commands: VecDeque::new(),
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Quit { .. } => {
+                self.close_requested = true;
+            }
+            // Backspace is held to rewind
+            Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. } => {
+                self.commands.push_back(Command::Rewind(true));
+            }
+            Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => {
+                self.commands.push_back(Command::Rewind(false));
+            }
+            Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                self.commands.push_back(Command::Break);
+            }
+            Event::KeyDown { keycode: Some(Keycode::P | Keycode::Pause), repeat: false, .. } => {
+                self.commands.push_back(Command::Pause);
+            }
+            // Repeats advance frame after frame while N is held
+            Event::KeyDown { keycode: Some(Keycode::N), .. } => {
+                self.commands.push_back(Command::FrameAdvance);
+            }
+            // Tab is held for turbo
+            Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+                self.commands.push_back(Command::Turbo(true));
+            }
+            Event::KeyUp { keycode: Some(Keycode::Tab), .. } => {
+                self.commands.push_back(Command::Turbo(false));
+            }
+            Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } => {
+                self.commands.push_back(Command::SlowMotion);
+            }
+            Event::KeyDown { keycode: Some(Keycode::Equals | Keycode::Plus | Keycode::KpPlus), .. } => {
+                self.commands.push_back(Command::IncreaseSpeed);
+            }
+            Event::KeyDown { keycode: Some(Keycode::Minus | Keycode::KpMinus), .. } => {
+                self.commands.push_back(Command::DecreaseSpeed);
+            }
+            // F1-F10 load a save state slot, holding shift saves to it instead
+            Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } => {
+                if let Some(slot) = function_key_slot(keycode) {
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        self.commands.push_back(Command::SaveState(slot));
+                    } else {
+                        self.commands.push_back(Command::LoadState(slot));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Keypad for SdlKeypad {
     fn update(&mut self, timeout_millis: u32) {
         // Use wait_event as to not busy-poll the event queue
         if let Some(event) = self.event_pump.wait_event_timeout(timeout_millis) {
-            if let Event::Quit { .. } = event {
-                self.close_requested = true;
-            }
+            self.handle_event(event);
 
             // When we do receive an event, make sure to exhaust the whole event queue
             while let Some(event) = self.event_pump.poll_event() {
-                if let Event::Quit { .. } = event {
-                    self.close_requested = true;
-                }
+                self.handle_event(event);
             }
         }
     }
 
+    fn is_key_pressed(&self, key: u8) -> bool {
+        let &keychar = self.keymap.get(&key).unwrap();
+        self.event_pump.keyboard_state().is_scancode_pressed(keychar_to_scancode(keychar))
+    }
+
+    fn should_close(&self) -> bool {
+        self.close_requested
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+}
+
+/// Audio output of SDL, playing the XO-CHIP audio pattern (a square wave by default)
+pub struct SdlBeeper {
+    audio_device: AudioDevice<PatternWave>,
+}
+
+impl SdlBeeper {
+    pub fn new(ctx: &Sdl) -> SdlBeeper {
+        let audio = ctx.audio().unwrap();
+        let audio_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None
+        };
+
+        let audio_device = audio.open_playback(None, &audio_spec, |spec| {
+            PatternWave {
+                pattern: [0xF0; 16],
+                sample_rate: spec.freq as f32,
+                phase_inc: pitch_to_rate(64) / spec.freq as f32,
+                phase: 0.0,
+                volume: 0.25
+            }
+        }).unwrap();
+
+        SdlBeeper { audio_device }
+    }
+}
+
+impl Beeper for SdlBeeper {
     fn sound_resume(&self) {
         self.audio_device.resume();
     }
@@ -150,6 +231,36 @@ impl Drawable for SDLGraphics {
     fn sound_pause(&self) {
         self.audio_device.pause();
     }
+
+    fn sound_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        let mut wave = self.audio_device.lock();
+        wave.pattern = *pattern;
+        wave.phase_inc = pitch_to_rate(pitch) / wave.sample_rate;
+    }
+}
+
+/// Colors of the pixels by the bitplanes they are set in: none, first, second, both
+const PALETTE: [Color; 4] = [
+    Color::RGB(0, 0, 0),
+    Color::RGB(255, 255, 255),
+    Color::RGB(170, 170, 170),
+    Color::RGB(85, 85, 85),
+];
+
+fn function_key_slot(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        Keycode::F10 => Some(10),
+        _ => None,
+    }
 }
 
 fn keychar_to_scancode(keychar: char) -> Scancode {
@@ -174,25 +285,33 @@ fn keychar_to_scancode(keychar: char) -> Scancode {
     }
 }
 
-// Square wave generation taken from https://rust-sdl2.github.io/rust-sdl2/sdl2/audio/index.html
-struct SquareWave {
+/// Playback rate in bits per second of the XO-CHIP audio pattern for the given pitch register value
+fn pitch_to_rate(pitch: u8) -> f32 {
+    4000.0 * 2.0_f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+// Plays the 128 bits XO-CHIP audio pattern in a loop, based on the square wave example
+// from https://rust-sdl2.github.io/rust-sdl2/sdl2/audio/index.html
+struct PatternWave {
+    pattern: [u8; 16],
+    sample_rate: f32,
     phase_inc: f32,
     phase: f32,
     volume: f32
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for PatternWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
+            let bit = self.phase as usize;
+            *x = if self.pattern[bit / 8] & (0b1000_0000 >> (bit % 8)) != 0 {
                 self.volume
             } else {
                 -self.volume
             };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            self.phase = (self.phase + self.phase_inc) % 128.0;
         }
     }
 }
\ No newline at end of file
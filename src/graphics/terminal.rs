@@ -0,0 +1,213 @@
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use super::terminal_keypad::{TerminalKeypad, PALETTE};
+use super::{Display, Keypad, Beeper, Command};
+
+/// Frontend playing games in a plain ANSI terminal, for quick tests over SSH or in tmux.
+///
+/// Every character shows two pixels stacked vertically with an upper half block, so the 64x32 screen
+/// takes 64x16 characters. The terminal bell rings when the sound starts
+pub struct TerminalGraphics {
+    width: usize,
+    height: usize,
+    keypad: TerminalKeypad,
+    /// Last screen drawn, to only redraw when it changes
+    drawn: Option<Vec<Vec<u8>>>,
+    status: String,
+    commands: VecDeque<Command>,
+    close_requested: bool,
+    turbo: bool,
+    rewinding: bool,
+    sound_on: Cell<bool>,
+}
+
+impl TerminalGraphics {
+    pub fn new(width: usize, height: usize, keymap: &HashMap<u8, char>) -> TerminalGraphics {
+        TerminalGraphics {
+            width,
+            height,
+            keypad: TerminalKeypad::new(keymap),
+            drawn: None,
+            status: String::new(),
+            commands: VecDeque::new(),
+            close_requested: false,
+            turbo: false,
+            rewinding: false,
+            sound_on: Cell::new(false),
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        let key = match event {
+            Event::Key(key) => key,
+            Event::Resize(..) => {
+                self.drawn = None;
+                return;
+            }
+            _ => return,
+        };
+        if key.code == KeyCode::Tab {
+            // Tab is held for turbo, or toggles it when releases aren't reported
+            let turbo = match key.kind {
+                KeyEventKind::Press if self.keypad.reports_release() => true,
+                KeyEventKind::Press => !self.turbo,
+                KeyEventKind::Release => false,
+                KeyEventKind::Repeat => return,
+            };
+            self.turbo = turbo;
+            self.commands.push_back(Command::Turbo(turbo));
+            return;
+        }
+        if key.code == KeyCode::Backspace {
+            // Backspace is held to rewind, or toggles it when releases aren't reported
+            let rewinding = match key.kind {
+                KeyEventKind::Press if self.keypad.reports_release() => true,
+                KeyEventKind::Press => !self.rewinding,
+                KeyEventKind::Release => false,
+                KeyEventKind::Repeat => return,
+            };
+            self.rewinding = rewinding;
+            self.commands.push_back(Command::Rewind(rewinding));
+            return;
+        }
+        if key.kind != KeyEventKind::Press {
+            self.keypad.handle(&key);
+            return;
+        }
+
+        match key.code {
+            KeyCode::Esc => self.close_requested = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.close_requested = true,
+            KeyCode::Char('p' | 'P') => self.commands.push_back(Command::Pause),
+            KeyCode::Char('n' | 'N') => self.commands.push_back(Command::FrameAdvance),
+            KeyCode::Char('m' | 'M') => self.commands.push_back(Command::SlowMotion),
+            KeyCode::Char('+' | '=') => self.commands.push_back(Command::IncreaseSpeed),
+            KeyCode::Char('-') => self.commands.push_back(Command::DecreaseSpeed),
+            // F1-F10 load a save state slot, holding shift saves to it instead
+            KeyCode::F(slot @ 1..=10) => {
+                if key.modifiers.contains(KeyModifiers::SHIFT) {
+                    self.commands.push_back(Command::SaveState(slot));
+                } else {
+                    self.commands.push_back(Command::LoadState(slot));
+                }
+            }
+            _ => self.keypad.handle(&key),
+        }
+    }
+}
+
+impl Display for TerminalGraphics {
+    fn init(&mut self) {
+        self.keypad.enable().expect("Failed to set up the terminal");
+        execute!(io::stdout(), EnterAlternateScreen, Hide, Clear(ClearType::All)).expect("Failed to set up the terminal");
+    }
+
+    fn finalize(&mut self) {
+        // Restoring the terminal is best effort, there is nothing else to do if it fails
+        let _ = execute!(io::stdout(), ResetColor, Show, LeaveAlternateScreen);
+        let _ = self.keypad.disable();
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn draw_screen(&mut self, vram: &Vec<Vec<u8>>) {
+        if self.drawn.as_ref() == Some(vram) {
+            return;
+        }
+        if self.drawn.as_ref().map(|drawn| (drawn.len(), drawn[0].len())) != Some((vram.len(), vram[0].len())) {
+            // Clear what is left of a larger screen after changing resolution
+            queue!(io::stdout(), Clear(ClearType::All)).expect("Failed to draw to the terminal");
+        }
+
+        // Build the whole frame before writing it, so it appears at once
+        let mut out = Vec::new();
+        for (row, pixels) in vram.chunks(2).enumerate() {
+            queue!(out, MoveTo(0, row as u16)).unwrap();
+            let mut colors = None;
+            for x in 0..pixels[0].len() {
+                let top = color(pixels[0][x]);
+                let bottom = pixels.get(1).map_or(color(0), |pixels| color(pixels[x]));
+                if colors != Some((top, bottom)) {
+                    queue!(out, SetForegroundColor(top), SetBackgroundColor(bottom)).unwrap();
+                    colors = Some((top, bottom));
+                }
+                queue!(out, Print('\u{2580}')).unwrap();
+            }
+            queue!(out, ResetColor).unwrap();
+        }
+        let row = vram.len().div_ceil(2) as u16;
+        queue!(out, MoveTo(0, row), Print("Esc quit  P pause  N next frame  Tab turbo  M slow motion  +/- speed  F1-F10 load  Shift+F1-F10 save")).unwrap();
+        queue!(out, MoveTo(0, row + 1), Print(&self.status), Clear(ClearType::UntilNewLine)).unwrap();
+
+        let mut stdout = io::stdout();
+        stdout.write_all(&out).and_then(|_| stdout.flush()).expect("Failed to draw to the terminal");
+        self.drawn = Some(vram.clone());
+    }
+
+    fn show_status(&mut self, status: &str) {
+        // Shown with the next frame
+        self.status = status.to_string();
+        self.drawn = None;
+    }
+}
+
+impl Keypad for TerminalGraphics {
+    fn update(&mut self, timeout_millis: u32) {
+        let mut timeout = Duration::from_millis(timeout_millis as u64);
+        while event::poll(timeout).unwrap_or(false) {
+            if let Ok(event) = event::read() {
+                self.handle_event(event);
+            }
+            timeout = Duration::ZERO;
+        }
+    }
+
+    fn is_key_pressed(&self, key: u8) -> bool {
+        self.keypad.is_pressed(key)
+    }
+
+    fn should_close(&self) -> bool {
+        self.close_requested
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+}
+
+impl Beeper for TerminalGraphics {
+    fn sound_resume(&self) {
+        if !self.sound_on.replace(true) {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+        }
+    }
+
+    fn sound_pause(&self) {
+        self.sound_on.set(false);
+    }
+
+    fn sound_pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) {
+        // The terminal bell can't play a waveform
+    }
+}
+
+fn color(pixel: u8) -> Color {
+    let (r, g, b) = PALETTE[pixel as usize & 0b11];
+    Color::Rgb { r, g, b }
+}
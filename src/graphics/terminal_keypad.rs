@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyboardEnhancementFlags, PushKeyboardEnhancementFlags, PopKeyboardEnhancementFlags};
+use crossterm::{execute, terminal};
+
+/// Colors of the pixels by the bitplanes they are set in, as in the SDL frontend
+pub(super) const PALETTE: [(u8, u8, u8); 4] = [(0, 0, 0), (255, 255, 255), (170, 170, 170), (85, 85, 85)];
+
+/// How long a key counts as held after it was pressed, when the terminal doesn't report key releases.
+/// Holding a key down makes the terminal repeat it, which keeps it held
+const HOLD_DURATION: Duration = Duration::from_millis(200);
+
+/// Keypad state of the terminal frontends.
+///
+/// Most terminals only report key presses, so keys are considered held for a short while after being
+/// pressed. Terminals supporting the kitty keyboard protocol also report releases, which are used instead
+pub(super) struct TerminalKeypad {
+    keymap: HashMap<char, u8>,
+    report_release: bool,
+    /// When each key was last pressed or repeated, None if it is released
+    pressed: [Option<Instant>; 16],
+}
+
+impl TerminalKeypad {
+    pub fn new(keymap: &HashMap<u8, char>) -> TerminalKeypad {
+        TerminalKeypad {
+            keymap: keymap.iter().map(|(&key, &keychar)| (keychar.to_ascii_lowercase(), key)).collect(),
+            report_release: false,
+            pressed: [None; 16],
+        }
+    }
+
+    /// Put the terminal in raw mode, asking it to report key releases if it can
+    pub fn enable(&mut self) -> io::Result<()> {
+        terminal::enable_raw_mode()?;
+        self.report_release = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if self.report_release {
+            execute!(io::stdout(), PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(())
+    }
+
+    pub fn disable(&mut self) -> io::Result<()> {
+        if self.report_release {
+            execute!(io::stdout(), PopKeyboardEnhancementFlags)?;
+        }
+        terminal::disable_raw_mode()
+    }
+
+    /// Update the keypad from a key event
+    pub fn handle(&mut self, event: &KeyEvent) {
+        let KeyCode::Char(keychar) = event.code else { return };
+        let Some(&key) = self.keymap.get(&keychar.to_ascii_lowercase()) else { return };
+        self.pressed[key as usize] = match event.kind {
+            KeyEventKind::Release => None,
+            KeyEventKind::Press | KeyEventKind::Repeat => Some(Instant::now()),
+        };
+    }
+
+    /// Whether the terminal reports key releases, otherwise held keys have to be guessed
+    pub fn reports_release(&self) -> bool {
+        self.report_release
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        match self.pressed[key as usize & 0xF] {
+            Some(pressed) => self.report_release || pressed.elapsed() < HOLD_DURATION,
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Stdout};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::{execute, terminal};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use crate::chip8::{Chip8, Registers};
+use crate::disasm;
+use crate::error::Chip8Error;
+use crate::scheduler::{self, Scheduler, FRAME_DURATION};
+
+use super::terminal_keypad::{TerminalKeypad, PALETTE};
+use super::{Display, Keypad, Beeper, Command};
+
+// Instructions listed around pc and rows of 16 bytes listed around i
+const DISASSEMBLY_BEFORE: usize = 8;
+const DISASSEMBLY_AFTER: usize = 32;
+const MEMORY_ROWS_BEFORE: isize = 4;
+const MEMORY_ROWS: usize = 32;
+
+const HELP: &str = "Space run/pause  Enter step  Backspace step back  PgUp/PgDn memory  Esc quit";
+
+/// Debugger frontend running in a terminal, usable over SSH.
+///
+/// The screen is drawn with half block characters next to panes showing the registers, the call stack,
+/// the disassembly around pc and the memory around i. It is driven by `run_tui`, which starts paused
+pub struct TuiGraphics {
+    width: usize,
+    height: usize,
+    terminal: Option<Terminal<CrosstermBackend<Stdout>>>,
+    keypad: TerminalKeypad,
+    vram: Vec<Vec<u8>>,
+    controls: VecDeque<Control>,
+    commands: VecDeque<Command>,
+    close_requested: bool,
+    sound_on: Cell<bool>,
+    memory_scroll: isize,
+}
+
+/// Requests from the user to the TUI run loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    TogglePause,
+    Step,
+    /// Go back one recorded frame, needs --rewind
+    StepBack,
+}
+
+/// State of the machine shown in the panes
+struct Panes {
+    reg: Registers,
+    stack: Vec<u16>,
+    /// Address, text and whether it is the instruction at pc
+    disassembly: Vec<(usize, String, bool)>,
+    memory_start: usize,
+    memory: Vec<u8>,
+    status: String,
+}
+
+impl TuiGraphics {
+    pub fn new(width: usize, height: usize, keymap: &HashMap<u8, char>) -> TuiGraphics {
+        TuiGraphics {
+            width,
+            height,
+            terminal: None,
+            keypad: TerminalKeypad::new(keymap),
+            vram: vec![vec![0; width]; height],
+            controls: VecDeque::new(),
+            commands: VecDeque::new(),
+            close_requested: false,
+            sound_on: Cell::new(false),
+            memory_scroll: 0,
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        let Event::Key(key) = event else { return };
+        if key.kind != KeyEventKind::Press {
+            self.keypad.handle(&key);
+            return;
+        }
+
+        match key.code {
+            KeyCode::Esc => self.close_requested = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.close_requested = true,
+            KeyCode::Char(' ') => self.controls.push_back(Control::TogglePause),
+            KeyCode::Enter => self.controls.push_back(Control::Step),
+            KeyCode::Backspace => self.controls.push_back(Control::StepBack),
+            KeyCode::PageUp => self.memory_scroll -= MEMORY_ROWS as isize / 2,
+            KeyCode::PageDown => self.memory_scroll += MEMORY_ROWS as isize / 2,
+            KeyCode::Home => self.memory_scroll = 0,
+            _ => self.keypad.handle(&key),
+        }
+    }
+
+    fn poll_control(&mut self) -> Option<Control> {
+        self.controls.pop_front()
+    }
+
+    fn render(&mut self, panes: &Panes) {
+        let Some(mut terminal) = self.terminal.take() else { return };
+        terminal.draw(|frame| self.draw_panes(frame, panes)).expect("Failed to draw to the terminal");
+        self.terminal = Some(terminal);
+    }
+
+    fn draw_panes(&self, frame: &mut Frame, panes: &Panes) {
+        let display_height = (self.vram.len() as u16).div_ceil(2) + 2;
+        let [top, bottom, status] = Layout::vertical([
+            Constraint::Length(display_height.max(14)),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ]).areas(frame.area());
+        let [display, side] = Layout::horizontal([
+            Constraint::Length(self.vram[0].len() as u16 + 2),
+            Constraint::Min(0),
+        ]).areas(top);
+        let [registers, stack] = Layout::vertical([Constraint::Length(12), Constraint::Min(0)]).areas(side);
+        let [disassembly, memory] = Layout::horizontal([Constraint::Length(36), Constraint::Min(0)]).areas(bottom);
+
+        frame.render_widget(self.display_pane(), display);
+        frame.render_widget(registers_pane(&panes.reg), registers);
+        frame.render_widget(stack_pane(panes), stack);
+        frame.render_widget(disassembly_pane(panes, disassembly), disassembly);
+        frame.render_widget(memory_pane(panes), memory);
+
+        let sound = if self.sound_on.get() { "  \u{266A}" } else { "" };
+        frame.render_widget(Line::from(format!("{}{}  |  {}", panes.status, sound, HELP)), status);
+    }
+
+    /// The screen, drawn with one upper half block character for every two rows of pixels
+    fn display_pane(&self) -> Paragraph<'static> {
+        let lines: Vec<Line> = self.vram
+            .chunks(2)
+            .map(|rows| {
+                let spans: Vec<Span> = (0..rows[0].len())
+                    .map(|x| {
+                        let top = color(rows[0][x]);
+                        let bottom = rows.get(1).map_or(color(0), |row| color(row[x]));
+                        Span::styled("\u{2580}", Style::default().fg(top).bg(bottom))
+                    })
+                    .collect();
+                Line::from(spans)
+            })
+            .collect();
+        Paragraph::new(lines).block(Block::bordered().title("Display"))
+    }
+}
+
+fn color(pixel: u8) -> Color {
+    let (r, g, b) = PALETTE[pixel as usize & 0b11];
+    Color::Rgb(r, g, b)
+}
+
+fn registers_pane(reg: &Registers) -> Paragraph<'static> {
+    let mut lines: Vec<Line> = (0..8)
+        .map(|x| Line::from(format!("V{:X} {:02X}    V{:X} {:02X}", x, reg.v[x], x + 8, reg.v[x + 8])))
+        .collect();
+    lines.push(Line::from(format!("I  {:04X}  PC {:04X}", reg.i, reg.pc)));
+    lines.push(Line::from(format!("SP {:X}  DT {:02X}  ST {:02X}", reg.sp, reg.dt, reg.st)));
+    Paragraph::new(lines).block(Block::bordered().title("Registers"))
+}
+
+fn stack_pane(panes: &Panes) -> Paragraph<'static> {
+    let lines: Vec<Line> = panes.stack
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(level, addr)| Line::from(format!("#{:<2} 0x{:03X}", level, addr)))
+        .collect();
+    Paragraph::new(lines).block(Block::bordered().title(format!("Stack (SP = {})", panes.reg.sp)))
+}
+
+/// Instructions around pc, scrolled to keep the current one in the upper part of the pane
+fn disassembly_pane(panes: &Panes, area: Rect) -> Paragraph<'static> {
+    let current = panes.disassembly.iter().position(|&(_, _, current)| current).unwrap_or(0);
+    let skip = current.saturating_sub(area.height.saturating_sub(2) as usize / 3);
+
+    let lines: Vec<Line> = panes.disassembly
+        .iter()
+        .skip(skip)
+        .map(|(addr, text, current)| {
+            let line = format!("{} 0x{:03X}  {}", if *current { "=>" } else { "  " }, addr, text);
+            if *current {
+                Line::styled(line, Style::default().add_modifier(Modifier::REVERSED))
+            } else {
+                Line::from(line)
+            }
+        })
+        .collect();
+    Paragraph::new(lines).block(Block::bordered().title("Disassembly"))
+}
+
+/// Hex dump of the memory, with the byte at i highlighted
+fn memory_pane(panes: &Panes) -> Paragraph<'static> {
+    let i = panes.reg.i as usize;
+    let lines: Vec<Line> = panes.memory
+        .chunks(16)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let row_addr = panes.memory_start + row * 16;
+            let mut spans = vec![Span::raw(format!("0x{:04X} ", row_addr))];
+            for (col, byte) in bytes.iter().enumerate() {
+                let text = format!(" {:02X}", byte);
+                if row_addr + col == i {
+                    spans.push(Span::raw(" "));
+                    spans.push(Span::styled(format!("{:02X}", byte), Style::default().add_modifier(Modifier::REVERSED)));
+                } else {
+                    spans.push(Span::raw(text));
+                }
+            }
+            Line::from(spans)
+        })
+        .collect();
+    Paragraph::new(lines).block(Block::bordered().title(format!("Memory (I = 0x{:04X})", i)))
+}
+
+impl Display for TuiGraphics {
+    fn init(&mut self) {
+        self.keypad.enable().expect("Failed to set up the terminal");
+        execute!(io::stdout(), terminal::EnterAlternateScreen).expect("Failed to set up the terminal");
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout())).expect("Failed to set up the terminal");
+        terminal.clear().expect("Failed to set up the terminal");
+        self.terminal = Some(terminal);
+    }
+
+    fn finalize(&mut self) {
+        self.terminal = None;
+        // Restoring the terminal is best effort, there is nothing else to do if it fails
+        let _ = execute!(io::stdout(), terminal::LeaveAlternateScreen);
+        let _ = self.keypad.disable();
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn draw_screen(&mut self, vram: &Vec<Vec<u8>>) {
+        self.vram.clone_from(vram);
+    }
+}
+
+impl Keypad for TuiGraphics {
+    fn update(&mut self, timeout_millis: u32) {
+        let mut timeout = Duration::from_millis(timeout_millis as u64);
+        while event::poll(timeout).unwrap_or(false) {
+            if let Ok(event) = event::read() {
+                self.handle_event(event);
+            }
+            timeout = Duration::ZERO;
+        }
+    }
+
+    fn is_key_pressed(&self, key: u8) -> bool {
+        self.keypad.is_pressed(key)
+    }
+
+    fn should_close(&self) -> bool {
+        self.close_requested
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+}
+
+impl Beeper for TuiGraphics {
+    fn sound_resume(&self) {
+        self.sound_on.set(true);
+    }
+
+    fn sound_pause(&self) {
+        self.sound_on.set(false);
+    }
+
+    fn sound_pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) {
+        // There is no sound in the terminal
+    }
+}
+
+/// Run `chip8` in the terminal debugger until the user quits, executing instructions at its frequency
+/// while not paused
+pub fn run_tui(chip8: &mut Chip8<TuiGraphics>) -> Result<(), Chip8Error> {
+    chip8.graphics_mut().init();
+    let result = tui_loop(chip8);
+    chip8.graphics_mut().finalize();
+    result
+}
+
+fn tui_loop(chip8: &mut Chip8<TuiGraphics>) -> Result<(), Chip8Error> {
+    let mut scheduler = Scheduler::new(chip8.freq());
+    let mut paused = true;
+    let mut status = "Paused".to_string();
+    render(chip8, &status);
+
+    while !chip8.graphics().should_close() {
+        let mut step = false;
+        let mut step_back = false;
+        while let Some(control) = chip8.graphics_mut().poll_control() {
+            match control {
+                Control::TogglePause => paused = !paused,
+                Control::Step => {
+                    paused = true;
+                    step = true;
+                }
+                Control::StepBack => {
+                    paused = true;
+                    step_back = true;
+                }
+            }
+            status = if paused { "Paused" } else { "Running" }.to_string();
+        }
+        if step_back {
+            if !chip8.rewind_frame() {
+                status = "No frames to step back to".to_string();
+            }
+            let vram = chip8.vram().to_vec();
+            chip8.graphics_mut().draw_screen(&vram);
+        }
+        if chip8.is_halted() {
+            paused = true;
+            status = "Halted".to_string();
+        }
+
+        if !paused {
+            let mut frame = scheduler.begin_frame();
+            while frame.has_cycles() && !chip8.is_halted() {
+                frame.executed += 1;
+                if let Err(err) = chip8.step() {
+                    paused = true;
+                    status = err.to_string();
+                    break;
+                }
+            }
+            chip8.finish_frame();
+            render(chip8, &status);
+            scheduler::sleep_until(scheduler.end_frame(frame.executed, true));
+        } else {
+            // Single steps don't make time pass, the timers only tick while running
+            if step && !chip8.is_halted() {
+                if let Err(err) = chip8.step() {
+                    status = err.to_string();
+                }
+                let vram = chip8.vram().to_vec();
+                chip8.graphics_mut().draw_screen(&vram);
+            }
+            chip8.graphics_mut().update(FRAME_DURATION.as_millis() as u32);
+            render(chip8, &status);
+            scheduler.reset();
+        }
+    }
+
+    Ok(())
+}
+
+fn render(chip8: &mut Chip8<TuiGraphics>, status: &str) {
+    let reg = chip8.registers().clone();
+    let memory = chip8.memory();
+
+    // The instructions before pc are a best guess, as instructions have no fixed alignment
+    let mut disassembly = Vec::new();
+    let mut addr = (reg.pc as usize).saturating_sub(DISASSEMBLY_BEFORE * 2);
+    while disassembly.len() < DISASSEMBLY_BEFORE + DISASSEMBLY_AFTER && addr + 1 < memory.len() {
+        let (text, len) = disasm::disassemble_at(memory, addr).unwrap_or_else(|| {
+            (format!("db 0x{:02X}, 0x{:02X}", memory[addr], memory[addr + 1]), 2)
+        });
+        disassembly.push((addr, text, addr == reg.pc as usize));
+        addr += len;
+    }
+
+    let scroll = chip8.graphics().memory_scroll;
+    let max_start = memory.len() - MEMORY_ROWS * 16;
+    let memory_start = (((reg.i as isize >> 4) - MEMORY_ROWS_BEFORE + scroll).max(0) as usize * 16).min(max_start);
+
+    let panes = Panes {
+        stack: chip8.stack()[..reg.sp as usize].to_vec(),
+        reg,
+        disassembly,
+        memory_start,
+        memory: memory[memory_start..memory_start + MEMORY_ROWS * 16].to_vec(),
+        status: status.to_string(),
+    };
+    chip8.graphics_mut().render(&panes);
+}
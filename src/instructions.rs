@@ -1,7 +1,9 @@
+use std::fmt;
+
 use arbitrary_int::u4;
 use arbitrary_int::u12;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Inst {
     SYS(u12),
     CLS,
@@ -38,6 +40,26 @@ pub enum Inst {
     LDBV(u4),
     LDIV(u4),
     LDVI(u4),
+
+    // SUPER-CHIP 1.1
+    SCD(u4),
+    SCR,
+    SCL,
+    EXIT,
+    LOW,
+    HIGH,
+    LDHFV(u4),
+    LDRV(u4),
+    LDVR(u4),
+
+    // XO-CHIP
+    SCU(u4),
+    SAVE(u4, u4),
+    LOAD(u4, u4),
+    LDIL,
+    PLANE(u4),
+    AUDIO,
+    PITCH(u4),
 }
 
 impl Inst {
@@ -45,12 +67,24 @@ impl Inst {
         let inst = match opcode {
             0x00E0 => Inst::CLS,
             0x00EE => Inst::RET,
+            0x00C0..=0x00CF => Inst::SCD(u4::extract_u16(opcode, 0)),
+            0x00D0..=0x00DF => Inst::SCU(u4::extract_u16(opcode, 0)),
+            0x00FB => Inst::SCR,
+            0x00FC => Inst::SCL,
+            0x00FD => Inst::EXIT,
+            0x00FE => Inst::LOW,
+            0x00FF => Inst::HIGH,
             0x0000..=0x0FFF => Inst::SYS(u12::extract_u16(opcode, 0)),
             0x1000..=0x1FFF => Inst::JP(u12::extract_u16(opcode, 0)),
             0x2000..=0x2FFF => Inst::CALL(u12::extract_u16(opcode, 0)),
             0x3000..=0x3FFF => Inst::SE(u4::extract_u16(opcode, 8), (opcode & 0x00FF) as u8),
             0x4000..=0x4FFF => Inst::SNE(u4::extract_u16(opcode, 8), (opcode & 0x00FF) as u8),
-            0x5000..=0x5FFF => Inst::SEV(u4::extract_u16(opcode, 8), u4::extract_u16(opcode, 4)),
+            0x5000..=0x5FFF => match opcode & 0x000F {
+                0x0 => Inst::SEV(u4::extract_u16(opcode, 8), u4::extract_u16(opcode, 4)),
+                0x2 => Inst::SAVE(u4::extract_u16(opcode, 8), u4::extract_u16(opcode, 4)),
+                0x3 => Inst::LOAD(u4::extract_u16(opcode, 8), u4::extract_u16(opcode, 4)),
+                _ => return None,
+            },
             0x6000..=0x6FFF => Inst::LD(u4::extract_u16(opcode, 8), (opcode & 0x00FF) as u8),
             0x7000..=0x7FFF => Inst::ADD(u4::extract_u16(opcode, 8), (opcode & 0x00FF) as u8),
             0x8000..=0x8FFF => match opcode & 0x000F {
@@ -83,19 +117,145 @@ impl Inst {
                 _ => return None,
             },
             0xF000..=0xFFFF => match opcode & 0x00FF {
+                0x00 if opcode == 0xF000 => Inst::LDIL,
+                0x02 if opcode == 0xF002 => Inst::AUDIO,
+                0x01 => Inst::PLANE(u4::extract_u16(opcode, 8)),
                 0x07 => Inst::LDVDT(u4::extract_u16(opcode, 8)),
                 0x0A => Inst::LDVKEY(u4::extract_u16(opcode, 8)),
                 0x15 => Inst::LDDTV(u4::extract_u16(opcode, 8)),
                 0x18 => Inst::LDSTV(u4::extract_u16(opcode, 8)),
                 0x1E => Inst::ADDIV(u4::extract_u16(opcode, 8)),
                 0x29 => Inst::LDFV(u4::extract_u16(opcode, 8)),
+                0x30 => Inst::LDHFV(u4::extract_u16(opcode, 8)),
+                0x3A => Inst::PITCH(u4::extract_u16(opcode, 8)),
                 0x33 => Inst::LDBV(u4::extract_u16(opcode, 8)),
                 0x55 => Inst::LDIV(u4::extract_u16(opcode, 8)),
                 0x65 => Inst::LDVI(u4::extract_u16(opcode, 8)),
+                0x75 => Inst::LDRV(u4::extract_u16(opcode, 8)),
+                0x85 => Inst::LDVR(u4::extract_u16(opcode, 8)),
                 _ => return None,
             }
         };
 
         Some(inst)
     }
+
+    /// Opcode of the instruction, the inverse of `decode`
+    pub fn encode(&self) -> u16 {
+        let x = |reg: u4| (reg.value() as u16) << 8;
+        let y = |reg: u4| (reg.value() as u16) << 4;
+
+        match *self {
+            Inst::SYS(addr) => addr.value(),
+            Inst::CLS => 0x00E0,
+            Inst::RET => 0x00EE,
+            Inst::JP(addr) => 0x1000 | addr.value(),
+            Inst::CALL(addr) => 0x2000 | addr.value(),
+            Inst::SE(reg, val) => 0x3000 | x(reg) | val as u16,
+            Inst::SNE(reg, val) => 0x4000 | x(reg) | val as u16,
+            Inst::SEV(reg1, reg2) => 0x5000 | x(reg1) | y(reg2),
+            Inst::LD(reg, val) => 0x6000 | x(reg) | val as u16,
+            Inst::ADD(reg, val) => 0x7000 | x(reg) | val as u16,
+            Inst::LDV(reg1, reg2) => 0x8000 | x(reg1) | y(reg2),
+            Inst::OR(reg1, reg2) => 0x8001 | x(reg1) | y(reg2),
+            Inst::AND(reg1, reg2) => 0x8002 | x(reg1) | y(reg2),
+            Inst::XOR(reg1, reg2) => 0x8003 | x(reg1) | y(reg2),
+            Inst::ADDV(reg1, reg2) => 0x8004 | x(reg1) | y(reg2),
+            Inst::SUB(reg1, reg2) => 0x8005 | x(reg1) | y(reg2),
+            Inst::SHR(reg1, reg2) => 0x8006 | x(reg1) | y(reg2),
+            Inst::SUBN(reg1, reg2) => 0x8007 | x(reg1) | y(reg2),
+            Inst::SHL(reg1, reg2) => 0x800E | x(reg1) | y(reg2),
+            Inst::SNEV(reg1, reg2) => 0x9000 | x(reg1) | y(reg2),
+            Inst::LDI(addr) => 0xA000 | addr.value(),
+            Inst::JPV(addr) => 0xB000 | addr.value(),
+            Inst::RND(reg, val) => 0xC000 | x(reg) | val as u16,
+            Inst::DRW(reg1, reg2, n) => 0xD000 | x(reg1) | y(reg2) | n.value() as u16,
+            Inst::SKP(reg) => 0xE09E | x(reg),
+            Inst::SKNP(reg) => 0xE0A1 | x(reg),
+            Inst::LDVDT(reg) => 0xF007 | x(reg),
+            Inst::LDVKEY(reg) => 0xF00A | x(reg),
+            Inst::LDDTV(reg) => 0xF015 | x(reg),
+            Inst::LDSTV(reg) => 0xF018 | x(reg),
+            Inst::ADDIV(reg) => 0xF01E | x(reg),
+            Inst::LDFV(reg) => 0xF029 | x(reg),
+            Inst::LDBV(reg) => 0xF033 | x(reg),
+            Inst::LDIV(reg) => 0xF055 | x(reg),
+            Inst::LDVI(reg) => 0xF065 | x(reg),
+            Inst::SCD(n) => 0x00C0 | n.value() as u16,
+            Inst::SCR => 0x00FB,
+            Inst::SCL => 0x00FC,
+            Inst::EXIT => 0x00FD,
+            Inst::LOW => 0x00FE,
+            Inst::HIGH => 0x00FF,
+            Inst::LDHFV(reg) => 0xF030 | x(reg),
+            Inst::LDRV(reg) => 0xF075 | x(reg),
+            Inst::LDVR(reg) => 0xF085 | x(reg),
+            Inst::SCU(n) => 0x00D0 | n.value() as u16,
+            Inst::SAVE(reg1, reg2) => 0x5002 | x(reg1) | y(reg2),
+            Inst::LOAD(reg1, reg2) => 0x5003 | x(reg1) | y(reg2),
+            Inst::LDIL => 0xF000,
+            Inst::PLANE(n) => 0xF001 | x(n),
+            Inst::AUDIO => 0xF002,
+            Inst::PITCH(reg) => 0xF03A | x(reg),
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Inst::SYS(addr) => write!(f, "SYS 0x{:03X}", addr.value()),
+            Inst::CLS => write!(f, "CLS"),
+            Inst::RET => write!(f, "RET"),
+            Inst::JP(addr) => write!(f, "JP 0x{:03X}", addr.value()),
+            Inst::CALL(addr) => write!(f, "CALL 0x{:03X}", addr.value()),
+            Inst::SE(reg, val) => write!(f, "SE V{:X}, 0x{:02X}", reg.value(), val),
+            Inst::SNE(reg, val) => write!(f, "SNE V{:X}, 0x{:02X}", reg.value(), val),
+            Inst::SEV(reg1, reg2) => write!(f, "SE V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::LD(reg, val) => write!(f, "LD V{:X}, 0x{:02X}", reg.value(), val),
+            Inst::ADD(reg, val) => write!(f, "ADD V{:X}, 0x{:02X}", reg.value(), val),
+            Inst::LDV(reg1, reg2) => write!(f, "LD V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::OR(reg1, reg2) => write!(f, "OR V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::AND(reg1, reg2) => write!(f, "AND V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::XOR(reg1, reg2) => write!(f, "XOR V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::ADDV(reg1, reg2) => write!(f, "ADD V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::SUB(reg1, reg2) => write!(f, "SUB V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::SHR(reg1, reg2) => write!(f, "SHR V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::SUBN(reg1, reg2) => write!(f, "SUBN V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::SHL(reg1, reg2) => write!(f, "SHL V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::SNEV(reg1, reg2) => write!(f, "SNE V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::LDI(addr) => write!(f, "LD I, 0x{:03X}", addr.value()),
+            Inst::JPV(addr) => write!(f, "JP V0, 0x{:03X}", addr.value()),
+            Inst::RND(reg, val) => write!(f, "RND V{:X}, 0x{:02X}", reg.value(), val),
+            Inst::DRW(reg1, reg2, n) => write!(f, "DRW V{:X}, V{:X}, {}", reg1.value(), reg2.value(), n.value()),
+            Inst::SKP(reg) => write!(f, "SKP V{:X}", reg.value()),
+            Inst::SKNP(reg) => write!(f, "SKNP V{:X}", reg.value()),
+            Inst::LDVDT(reg) => write!(f, "LD V{:X}, DT", reg.value()),
+            Inst::LDVKEY(reg) => write!(f, "LD V{:X}, K", reg.value()),
+            Inst::LDDTV(reg) => write!(f, "LD DT, V{:X}", reg.value()),
+            Inst::LDSTV(reg) => write!(f, "LD ST, V{:X}", reg.value()),
+            Inst::ADDIV(reg) => write!(f, "ADD I, V{:X}", reg.value()),
+            Inst::LDFV(reg) => write!(f, "LD F, V{:X}", reg.value()),
+            Inst::LDBV(reg) => write!(f, "LD B, V{:X}", reg.value()),
+            Inst::LDIV(reg) => write!(f, "LD [I], V{:X}", reg.value()),
+            Inst::LDVI(reg) => write!(f, "LD V{:X}, [I]", reg.value()),
+            Inst::SCD(n) => write!(f, "SCD {}", n.value()),
+            Inst::SCR => write!(f, "SCR"),
+            Inst::SCL => write!(f, "SCL"),
+            Inst::EXIT => write!(f, "EXIT"),
+            Inst::LOW => write!(f, "LOW"),
+            Inst::HIGH => write!(f, "HIGH"),
+            Inst::LDHFV(reg) => write!(f, "LD HF, V{:X}", reg.value()),
+            Inst::LDRV(reg) => write!(f, "LD R, V{:X}", reg.value()),
+            Inst::LDVR(reg) => write!(f, "LD V{:X}, R", reg.value()),
+            Inst::SCU(n) => write!(f, "SCU {}", n.value()),
+            Inst::SAVE(reg1, reg2) => write!(f, "SAVE V{:X}, V{:X}", reg1.value(), reg2.value()),
+            Inst::LOAD(reg1, reg2) => write!(f, "LOAD V{:X}, V{:X}", reg1.value(), reg2.value()),
+            // The address is stored in the word following the opcode
+            Inst::LDIL => write!(f, "LD I, LONG"),
+            Inst::PLANE(n) => write!(f, "PLANE {}", n.value()),
+            Inst::AUDIO => write!(f, "AUDIO"),
+            Inst::PITCH(reg) => write!(f, "PITCH V{:X}", reg.value()),
+        }
+    }
 }
@@ -0,0 +1,22 @@
+mod chip8;
+pub mod asm;
+pub mod debugger;
+pub mod disasm;
+mod error;
+pub mod gdb;
+mod instructions;
+pub mod octo;
+mod quirks;
+mod rewind;
+mod rng;
+mod scheduler;
+pub mod trace;
+pub mod graphics;
+
+pub use crate::chip8::{Chip8, Registers};
+pub use crate::error::{Chip8Error, StateError, AsmError};
+pub use crate::instructions::Inst;
+pub use crate::quirks::Quirks;
+pub use crate::rewind::RewindBuffer;
+pub use crate::scheduler::{FrameStats, FRAME_RATE};
+pub use crate::graphics::{Drawable, Display, Keypad, Beeper, Frontend, Command};
@@ -1,29 +1,170 @@
-mod chip8;
-mod instructions;
-mod graphics;
-
-use chip8::Chip8;
-use clap::Parser;
-use std::{fs, collections::HashMap};
-use graphics::{SDLGraphics};
+use chip8::{Chip8, Drawable, Quirks};
+use chip8::debugger::Debugger;
+use chip8::trace::Tracer;
+#[cfg(feature = "sdl")]
+use chip8::graphics::SDLGraphics;
+#[cfg(feature = "terminal")]
+use chip8::graphics::{TerminalGraphics, TuiGraphics, run_tui};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{env, fs, collections::{BTreeMap, HashMap}, io::BufWriter, ops::RangeInclusive, path::{Path, PathBuf}};
 use anyhow::anyhow;
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to chip8 program
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to chip8 program, or to Octo source (.8o) which is compiled before running
     #[arg(required = true)]
-    file: String,
+    file: Option<String>,
 
     /// Clock frequency in Hz, valid values are in the range [0, 1_000_000_000).
-    /// If the value is 0 the program will run at maximum speed and will not enforce any clockspeed
-    #[arg(short, long, default_value_t = 500)]
-    freq: usize,
+    /// If the value is 0 the program will run at maximum speed and will not enforce any clockspeed.
+    /// Defaults to the speed last chosen with +/- for the ROM, or 500
+    #[arg(short, long)]
+    freq: Option<usize>,
 
     /// Size of a game pixel (in screen pixels)
     #[arg(short, long, default_value_t = 20)]
     pixel_size: usize,
+
+    /// Force the 64x64 hires mode of the COSMAC VIP, detected automatically for ROMs starting with the
+    /// standard hires entry point
+    #[arg(long)]
+    hires: bool,
+
+    /// Seconds of gameplay that can be rewound by holding backspace, 0 disables rewinding
+    #[arg(long, default_value_t = 30)]
+    rewind: usize,
+
+    /// Speed multiplier while holding tab, 0 runs as fast as possible
+    #[arg(long, default_value_t = 4.0)]
+    turbo: f64,
+
+    /// Seed for the random number generator, to make runs reproducible. A random seed is used if omitted
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Start in the interactive debugger, with a command prompt on the terminal. Type help at the prompt
+    /// for the list of commands
+    #[arg(long)]
+    debug: bool,
+
+    /// Where to play the game, defaults to sdl when built with SDL support
+    #[arg(long, value_enum)]
+    frontend: Option<Frontend>,
+
+    /// Instead of running the program, wait for GDB to connect on the given address (e.g. 127.0.0.1:1234)
+    /// and let it control the emulator through the remote serial protocol
+    #[arg(long, value_name = "ADDR")]
+    gdb: Option<String>,
+
+    /// Log every executed instruction to the given file, with the registers and memory it changed
+    #[arg(long, value_name = "FILE")]
+    trace: Option<String>,
+
+    /// Only trace the instructions in the given address range, e.g. 0x200-0x2FF
+    #[arg(long, value_name = "START-END", value_parser = parse_addr_range, requires = "trace")]
+    trace_addrs: Option<RangeInclusive<u16>>,
+
+    /// Only trace the given instructions, e.g. DRW,CALL,RET
+    #[arg(long, value_name = "MNEMONICS", value_delimiter = ',', requires = "trace")]
+    trace_inst: Option<Vec<String>>,
+
+    /// Only keep the last N instructions, and write them to the trace when the program faults
+    #[arg(long, value_name = "N", requires = "trace")]
+    trace_last: Option<usize>,
+
+    /// Interpreter whose behaviour to emulate for ambiguous instructions
+    #[arg(short, long, value_enum, default_value_t = Platform::Legacy)]
+    quirks: Platform,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Assemble a chip8 program
+    Asm {
+        /// Path to the assembly source
+        file: String,
+
+        /// Path of the assembled program, defaults to the source path with the .ch8 extension
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Address the program is loaded at
+        #[arg(long, default_value_t = 0x200)]
+        origin: u16,
+    },
+
+    /// Print an annotated listing of a chip8 program
+    Disasm {
+        /// Path to chip8 program
+        file: String,
+
+        /// Address the program is loaded at
+        #[arg(long, default_value_t = 0x200)]
+        origin: u16,
+
+        /// Print assembler source, without the address and raw byte columns
+        #[arg(long)]
+        source: bool,
+    },
+}
+
+/// Frontends selectable from the command line
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Frontend {
+    /// Window drawn with SDL
+    #[cfg(feature = "sdl")]
+    Sdl,
+    /// Plain ANSI terminal, drawn with half block characters
+    #[cfg(feature = "terminal")]
+    Terminal,
+    /// Terminal debugger, with panes showing the registers, the call stack, the disassembly and the memory
+    #[cfg(feature = "terminal")]
+    Tui,
+}
+
+impl Frontend {
+    #[cfg(feature = "sdl")]
+    const DEFAULT: Option<Frontend> = Some(Frontend::Sdl);
+    #[cfg(all(not(feature = "sdl"), feature = "terminal"))]
+    const DEFAULT: Option<Frontend> = Some(Frontend::Terminal);
+    #[cfg(not(any(feature = "sdl", feature = "terminal")))]
+    const DEFAULT: Option<Frontend> = None;
+}
+
+/// Quirks presets selectable from the command line
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Platform {
+    /// Behaviour of earlier versions of this emulator
+    Legacy,
+    /// COSMAC VIP
+    Vip,
+    /// CHIP-48
+    Chip48,
+    /// SUPER-CHIP 1.1
+    Schip,
+    /// XO-CHIP
+    XoChip,
+}
+
+impl Platform {
+    fn quirks(self) -> Quirks {
+        match self {
+            Platform::Legacy => Quirks::legacy(),
+            Platform::Vip => Quirks::cosmac_vip(),
+            Platform::Chip48 => Quirks::chip48(),
+            Platform::Schip => Quirks::schip(),
+            Platform::XoChip => Quirks::xo_chip(),
+        }
+    }
 }
 
+/// Clock frequency in Hz when not given on the command line nor saved for the ROM
+const DEFAULT_FREQ: usize = 500;
+
 /// Default keybindings
 const KEYBINDINGS: [(u8, char); 16] = [
     (0x1, '1'),
@@ -47,18 +188,206 @@ const KEYBINDINGS: [(u8, char); 16] = [
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    if args.freq > 1_000_000_000 {
+    match args.command {
+        Some(Command::Asm { ref file, ref output, origin }) => asm(file, output.as_deref(), origin),
+        Some(Command::Disasm { ref file, origin, source }) => disasm(file, origin, source),
+        None => {
+            let file = args.file.clone().expect("Path to chip8 program is required");
+            run(&args, &file)
+        }
+    }
+}
+
+fn asm(file: &str, output: Option<&str>, origin: u16) -> anyhow::Result<()> {
+    let source = fs::read_to_string(file)?;
+    let rom = chip8::asm::assemble(&source, origin).map_err(|err| anyhow!("{}:{}", file, err))?;
+
+    let output = match output {
+        Some(output) => output.into(),
+        None => Path::new(file).with_extension("ch8"),
+    };
+    fs::write(output, rom)?;
+    Ok(())
+}
+
+fn disasm(file: &str, origin: u16, source: bool) -> anyhow::Result<()> {
+    let rom = fs::read(file)?;
+    if source {
+        print!("{}", chip8::disasm::disassemble_source(&rom, origin));
+    } else {
+        print!("{}", chip8::disasm::disassemble(&rom, origin));
+    }
+    Ok(())
+}
+
+fn run(args: &Args, file: &str) -> anyhow::Result<()> {
+    if args.freq.is_some_and(|freq| freq > 1_000_000_000) {
         return Err(anyhow!("Frequency too high, max is 1,000,000,000"));
     }
+    if !(args.turbo.is_finite() && args.turbo >= 0.0) {
+        return Err(anyhow!("Turbo speed must be a positive number, or 0"));
+    }
 
     let keymap = HashMap::from(KEYBINDINGS);
 
-    let gfx = SDLGraphics::new(64, 32, args.pixel_size as u32, keymap); 
-    
-    let rom = fs::read(&args.file)?;
-    let mut chip8 = Chip8::with_rom(args.freq, gfx, &rom);
-    chip8.run();
+    let rom = if Path::new(file).extension().is_some_and(|ext| ext == "8o") {
+        let source = fs::read_to_string(file)?;
+        chip8::octo::compile(&source).map_err(|err| anyhow!("{}:{}", file, err))?
+    } else {
+        fs::read(file)?
+    };
+
+    // Hires programs for the COSMAC VIP start with a jump to 0x260 and need a square display
+    let vip_hires = args.hires || rom.starts_with(&[0x12, 0x60]);
+    let height = if vip_hires { 64 } else { 32 };
+
+    match args.frontend.or(Frontend::DEFAULT) {
+        #[cfg(feature = "sdl")]
+        Some(Frontend::Sdl) => {
+            let gfx = SDLGraphics::new(64, height as u32, args.pixel_size as u32, keymap);
+            run_with(args, file, &rom, gfx, |chip8| play(args, chip8))
+        }
+        #[cfg(feature = "terminal")]
+        Some(Frontend::Terminal) => {
+            let gfx = TerminalGraphics::new(64, height, &keymap);
+            run_with(args, file, &rom, gfx, |chip8| play(args, chip8))
+        }
+        #[cfg(feature = "terminal")]
+        Some(Frontend::Tui) => {
+            // The TUI steps through the program itself, instead of the run loop consulting a debugger
+            if args.debug || args.gdb.is_some() {
+                return Err(anyhow!("The tui frontend is a debugger already, it can't be used with --debug or --gdb"));
+            }
+            let gfx = TuiGraphics::new(64, height, &keymap);
+            run_with(args, file, &rom, gfx, |chip8| Ok(run_tui(chip8)?))
+        }
+        None => Err(anyhow!("Built without any frontend, enable the sdl or terminal feature")),
+    }
+}
+
+/// Run the game, or let GDB drive it if requested
+fn play<T: Drawable>(args: &Args, chip8: &mut Chip8<T>) -> anyhow::Result<()> {
+    match args.gdb {
+        Some(ref addr) => Ok(chip8::gdb::serve(chip8, addr.as_str())?),
+        None => Ok(chip8.run()?),
+    }
+}
 
+/// Set up the emulator as requested on the command line and run it with `run`
+fn run_with<T: Drawable>(
+    args: &Args,
+    file: &str,
+    rom: &[u8],
+    gfx: T,
+    run: impl FnOnce(&mut Chip8<T>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut chip8 = Chip8::with_rom(DEFAULT_FREQ, gfx, args.quirks.quirks(), rom)?;
+    let saved_freq = saved_speed(chip8.rom_hash());
+    if let Some(freq) = args.freq.or(saved_freq) {
+        chip8.set_freq(freq);
+    }
+    if args.hires {
+        chip8.set_vip_hires(true);
+    }
+    if let Some(seed) = args.seed {
+        chip8.seed_rng(seed);
+    }
+    chip8.set_state_path(file);
+    chip8.set_rewind_depth(args.rewind * 60);
+    chip8.set_turbo_speed(args.turbo);
+    if args.debug {
+        chip8.set_debugger(Debugger::new());
+    }
+    if let Some(ref path) = args.trace {
+        let mut tracer = Tracer::new(Box::new(BufWriter::new(fs::File::create(path)?)));
+        if let Some(ref addrs) = args.trace_addrs {
+            tracer.set_addrs(addrs.clone());
+        }
+        if let Some(ref mnemonics) = args.trace_inst {
+            tracer.set_mnemonics(mnemonics);
+        }
+        if let Some(len) = args.trace_last {
+            tracer.set_last(len);
+        }
+        chip8.set_tracer(tracer);
+    }
+
+    // SUPER-CHIP RPL flags are kept next to the ROM so they survive between runs
+    let rpl_path = format!("{}.rpl", file);
+    if let Ok(flags) = fs::read(&rpl_path) {
+        if let Ok(flags) = flags.try_into() {
+            chip8.set_rpl_flags(flags);
+        }
+    }
+    let initial_rpl = *chip8.rpl_flags();
+
+    let result = run(&mut chip8);
+
+    // Failing to persist settings is only reported, so it doesn't hide how the emulator stopped
+    if *chip8.rpl_flags() != initial_rpl {
+        if let Err(err) = fs::write(&rpl_path, chip8.rpl_flags()) {
+            eprintln!("Failed to save the RPL flags to {}: {}", rpl_path, err);
+        }
+    }
+    if chip8.freq() != args.freq.or(saved_freq).unwrap_or(DEFAULT_FREQ) {
+        if let Err(err) = save_speed(chip8.rom_hash(), chip8.freq()) {
+            eprintln!("Failed to save the speed: {}", err);
+        }
+    }
+
+    result
+}
+
+/// File keeping the speed chosen with +/- for every ROM, as lines of ROM hash and frequency
+fn speeds_path() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config.join("chip8").join("speeds"))
+}
+
+fn load_speeds(path: &Path) -> BTreeMap<u64, usize> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return BTreeMap::new();
+    };
+    // Lines that don't parse are skipped, and dropped the next time the file is written
+    text.lines()
+        .filter_map(|line| {
+            let (hash, freq) = line.split_once(' ')?;
+            Some((u64::from_str_radix(hash, 16).ok()?, freq.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Speed last chosen for the ROM with the given hash
+fn saved_speed(rom_hash: u64) -> Option<usize> {
+    load_speeds(&speeds_path()?).get(&rom_hash).copied()
+}
+
+fn save_speed(rom_hash: u64, freq: usize) -> anyhow::Result<()> {
+    let Some(path) = speeds_path() else {
+        return Ok(());
+    };
+    let mut speeds = load_speeds(&path);
+    speeds.insert(rom_hash, freq);
+    let text: String = speeds.iter().map(|(hash, freq)| format!("{:016x} {}\n", hash, freq)).collect();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, text)?;
     Ok(())
 }
 
+/// Parse an address range such as `0x200-0x2FF`
+fn parse_addr_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |addr: &str| {
+        let parsed = match addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => addr.parse(),
+        };
+        parsed.map_err(|_| format!("Invalid address '{}'", addr))
+    };
+    let (start, end) = text.split_once('-').ok_or("Expected a range such as 0x200-0x2FF")?;
+    Ok(parse(start)?..=parse(end)?)
+}
@@ -0,0 +1,839 @@
+use std::collections::{HashMap, VecDeque};
+
+use arbitrary_int::{u4, u12};
+
+use crate::error::AsmError;
+use crate::instructions::Inst;
+
+const ORIGIN: usize = 0x200;
+
+/// Maximum number of macro expansions, to stop macros that invoke themselves forever
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// Compile a program written in Octo, the high level assembly language used by most modern CHIP-8 and
+/// XO-CHIP programs, into a ROM to be loaded at 0x200.
+///
+/// Supported are all the instructions, labels (`: name`), `:alias`, `:const`, `:calc`, `:macro`, `:org`,
+/// `:byte`, `:call`, `:unpack`, structured control flow (`if ... then`, `if ... begin ... else ... end`,
+/// `loop ... while ... again`) and sprite data written as plain numbers. If a `main` label is defined
+/// the program starts with a jump to it
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError> {
+    let tokens: VecDeque<Token> = source
+        .lines()
+        .enumerate()
+        .flat_map(|(number, line)| {
+            let line_number = number + 1;
+            line.split('#')
+                .next()
+                .unwrap()
+                .split_whitespace()
+                .map(move |text| Token { text: text.to_string(), line: line_number })
+        })
+        .collect();
+
+    let has_main = tokens.iter().zip(tokens.iter().skip(1)).any(|(a, b)| a.text == ":" && b.text == "main");
+
+    let mut compiler = Compiler {
+        tokens,
+        line: 0,
+        here: ORIGIN,
+        rom: Vec::new(),
+        written: Vec::new(),
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        flow: Vec::new(),
+        expansions: 0,
+    };
+
+    if has_main {
+        compiler.fixup(Fixup::Addr, "main".to_string());
+        compiler.inst(Inst::JP(u12::new(0)))?;
+    }
+
+    while !compiler.tokens.is_empty() {
+        compiler.statement()?;
+    }
+
+    if let Some(flow) = compiler.flow.last() {
+        compiler.line = flow.line();
+        let message = match flow {
+            Flow::If { .. } | Flow::Else { .. } => "'begin' without a matching 'end'",
+            Flow::Loop { .. } => "'loop' without a matching 'again'",
+        };
+        return compiler.error(message.to_string());
+    }
+
+    compiler.resolve()
+}
+
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// Reference to a label that might not be defined yet, patched once the whole program is compiled
+#[derive(Clone, Copy)]
+enum Fixup {
+    /// 12-bit address in the low bits of an instruction
+    Addr,
+    /// 16-bit address following `i := long`
+    Long,
+    /// Address split by `:unpack` into the immediates of two consecutive `vx := n` instructions, with the
+    /// given nibble as the high half of the first one
+    Unpack(u8),
+}
+
+enum Flow {
+    /// `if ... begin`, with the position of the jump over the block
+    If { jump: usize, line: usize },
+    /// `else`, with the position of the jump over the else block
+    Else { jump: usize, line: usize },
+    /// `loop`, with its start address and the position of the jumps out of it made by `while`
+    Loop { start: usize, breaks: Vec<usize>, line: usize },
+}
+
+impl Flow {
+    fn line(&self) -> usize {
+        match *self {
+            Flow::If { line, .. } | Flow::Else { line, .. } | Flow::Loop { line, .. } => line,
+        }
+    }
+}
+
+/// A condition of `if` or `while`, compiled to the instructions preparing it followed by a skip
+struct Condition {
+    setup: Vec<Inst>,
+    /// Skips the next instruction when the condition is false
+    skip_if_false: Inst,
+    /// Skips the next instruction when the condition is true
+    skip_if_true: Inst,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    /// Address the next byte is written at
+    here: usize,
+    /// Program, starting at 0x200
+    rom: Vec<u8>,
+    written: Vec<bool>,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u4>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, Fixup, String, usize)>,
+    flow: Vec<Flow>,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn error<T>(&self, message: String) -> Result<T, AsmError> {
+        Err(AsmError { line: self.line, message })
+    }
+
+    fn next(&mut self) -> Result<String, AsmError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("unexpected end of file".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("expected '{}', found '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+
+        if let Some(reg) = self.register(&token) {
+            return self.register_statement(reg);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.contains_key(&name) {
+                    return self.error(format!("label '{}' is already defined", name));
+                }
+                self.labels.insert(name, self.here);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let Some(reg) = self.register(&token) else {
+                    return self.error(format!("'{}' is not a register", token));
+                };
+                self.aliases.insert(name, reg);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let value = self.constant(&token)?;
+                self.define_constant(name, value)?;
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                // Unlike constants, calculated values can be redefined, as it is handy in macros
+                self.constants.insert(name, value);
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut params = Vec::new();
+                while self.peek().is_some_and(|token| token != "{") {
+                    params.push(self.next()?);
+                }
+                let body = self.block()?;
+                self.macros.insert(name, Macro { params, body });
+            }
+            ":org" => {
+                let addr = self.value_or_calc()?;
+                if !(ORIGIN as i64..=0xFFFF).contains(&addr) {
+                    return self.error(format!("address 0x{:X} out of range", addr));
+                }
+                self.here = addr as usize;
+            }
+            ":byte" => {
+                let value = self.value_or_calc()?;
+                let byte = self.check(value, -0x80, 0xFF)? as u8;
+                self.emit(byte)?;
+            }
+            ":call" => {
+                let addr = self.addr()?;
+                self.inst(Inst::CALL(addr))?;
+            }
+            ":unpack" => {
+                let token = self.next()?;
+                let nibble = self.check(self.constant(&token)? as i64, 0, 0xF)? as u8;
+                let addr = self.next()?;
+                match self.lookup(&addr) {
+                    Some(addr) => {
+                        let addr = self.check(addr as i64, 0, 0xFFF)? as u16;
+                        self.inst(Inst::LD(u4::new(0), (nibble << 4) | (addr >> 8) as u8))?;
+                        self.inst(Inst::LD(u4::new(1), addr as u8))?;
+                    }
+                    None => {
+                        self.fixup(Fixup::Unpack(nibble), addr);
+                        self.inst(Inst::LD(u4::new(0), 0))?;
+                        self.inst(Inst::LD(u4::new(1), 0))?;
+                    }
+                }
+            }
+            "clear" => self.inst(Inst::CLS)?,
+            "return" | ";" => self.inst(Inst::RET)?,
+            "exit" => self.inst(Inst::EXIT)?,
+            "hires" => self.inst(Inst::HIGH)?,
+            "lores" => self.inst(Inst::LOW)?,
+            "scroll-right" => self.inst(Inst::SCR)?,
+            "scroll-left" => self.inst(Inst::SCL)?,
+            "audio" => self.inst(Inst::AUDIO)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.inst(Inst::SCD(n))?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.inst(Inst::SCU(n))?;
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                if n.value() > 3 {
+                    return self.error("plane must be between 0 and 3".to_string());
+                }
+                self.inst(Inst::PLANE(n))?;
+            }
+            "jump" => {
+                let addr = self.addr()?;
+                self.inst(Inst::JP(addr))?;
+            }
+            "jump0" => {
+                let addr = self.addr()?;
+                self.inst(Inst::JPV(addr))?;
+            }
+            "native" => {
+                let addr = self.addr()?;
+                self.inst(Inst::SYS(addr))?;
+            }
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.nibble()?;
+                self.inst(Inst::DRW(x, y, n))?;
+            }
+            "bcd" => {
+                let reg = self.expect_register()?;
+                self.inst(Inst::LDBV(reg))?;
+            }
+            "save" | "load" => {
+                let x = self.expect_register()?;
+                let inst = if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.expect_register()?;
+                    if token == "save" { Inst::SAVE(x, y) } else { Inst::LOAD(x, y) }
+                } else if token == "save" {
+                    Inst::LDIV(x)
+                } else {
+                    Inst::LDVI(x)
+                };
+                self.inst(inst)?;
+            }
+            "saveflags" => {
+                let reg = self.expect_register()?;
+                self.inst(Inst::LDRV(reg))?;
+            }
+            "loadflags" => {
+                let reg = self.expect_register()?;
+                self.inst(Inst::LDVR(reg))?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let reg = self.expect_register()?;
+                let inst = match token.as_str() {
+                    "delay" => Inst::LDDTV(reg),
+                    "buzzer" => Inst::LDSTV(reg),
+                    _ => Inst::PITCH(reg),
+                };
+                self.inst(inst)?;
+            }
+            "i" => self.i_statement()?,
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+                for inst in condition.setup {
+                    self.inst(inst)?;
+                }
+                match keyword.as_str() {
+                    "then" => self.inst(condition.skip_if_false)?,
+                    "begin" => {
+                        self.inst(condition.skip_if_true)?;
+                        let jump = self.placeholder_jump()?;
+                        self.flow.push(Flow::If { jump, line: self.line });
+                    }
+                    _ => return self.error(format!("expected 'then' or 'begin', found '{}'", keyword)),
+                }
+            }
+            "else" => {
+                let Some(Flow::If { jump: if_jump, .. }) = self.flow.pop() else {
+                    return self.error("'else' without a matching 'if ... begin'".to_string());
+                };
+                let jump = self.placeholder_jump()?;
+                self.patch_jump(if_jump, self.here)?;
+                self.flow.push(Flow::Else { jump, line: self.line });
+            }
+            "end" => {
+                let (Some(Flow::If { jump, .. }) | Some(Flow::Else { jump, .. })) = self.flow.pop() else {
+                    return self.error("'end' without a matching 'begin'".to_string());
+                };
+                self.patch_jump(jump, self.here)?;
+            }
+            "loop" => self.flow.push(Flow::Loop { start: self.here, breaks: Vec::new(), line: self.line }),
+            "while" => {
+                let condition = self.condition()?;
+                for inst in condition.setup {
+                    self.inst(inst)?;
+                }
+                self.inst(condition.skip_if_true)?;
+                let jump = self.placeholder_jump()?;
+                let Some(Flow::Loop { breaks, .. }) = self.flow.iter_mut().rev().find(|flow| matches!(flow, Flow::Loop { .. })) else {
+                    return self.error("'while' outside of a loop".to_string());
+                };
+                breaks.push(jump);
+            }
+            "again" => {
+                let Some(Flow::Loop { start, breaks, .. }) = self.flow.pop() else {
+                    return self.error("'again' without a matching 'loop'".to_string());
+                };
+                let start = self.check(start as i64, 0, 0xFFF)? as u16;
+                self.inst(Inst::JP(u12::new(start)))?;
+                for jump in breaks {
+                    self.patch_jump(jump, self.here)?;
+                }
+            }
+            _ => {
+                if let Some(value) = parse_number(&token).or_else(|| self.constants.get(&token).copied()) {
+                    // Plain numbers are data, typically sprites
+                    let byte = self.check(value as i64, -0x80, 0xFF)? as u8;
+                    self.emit(byte)?;
+                } else if self.macros.contains_key(&token) {
+                    self.expand(&token)?;
+                } else if is_name(&token) {
+                    // Anything else is a call to a subroutine, which might be defined later on
+                    let addr = self.resolve_addr(token)?;
+                    self.inst(Inst::CALL(addr))?;
+                } else {
+                    return self.error(format!("unexpected '{}'", token));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u4) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let token = self.next()?;
+        let y = self.register(&token);
+
+        let inst = match (op.as_str(), y) {
+            (":=", Some(y)) => Inst::LDV(x, y),
+            (":=", None) => match token.as_str() {
+                "delay" => Inst::LDVDT(x),
+                "key" => Inst::LDVKEY(x),
+                "random" => {
+                    let mask = self.byte()?;
+                    Inst::RND(x, mask)
+                }
+                _ => Inst::LD(x, self.byte_value(&token)?),
+            },
+            ("+=", Some(y)) => Inst::ADDV(x, y),
+            ("+=", None) => Inst::ADD(x, self.byte_value(&token)?),
+            ("-=", Some(y)) => Inst::SUB(x, y),
+            ("-=", None) => Inst::ADD(x, self.byte_value(&token)?.wrapping_neg()),
+            ("=-", Some(y)) => Inst::SUBN(x, y),
+            ("|=", Some(y)) => Inst::OR(x, y),
+            ("&=", Some(y)) => Inst::AND(x, y),
+            ("^=", Some(y)) => Inst::XOR(x, y),
+            (">>=", Some(y)) => Inst::SHR(x, y),
+            ("<<=", Some(y)) => Inst::SHL(x, y),
+            _ => return self.error(format!("invalid operation '{} {}'", op, token)),
+        };
+
+        self.inst(inst)
+    }
+
+    fn i_statement(&mut self) -> Result<(), AsmError> {
+        let op = self.next()?;
+        match op.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let font = self.next()?;
+                    let reg = self.expect_register()?;
+                    self.inst(if font == "hex" { Inst::LDFV(reg) } else { Inst::LDHFV(reg) })
+                }
+                Some("long") => {
+                    self.next()?;
+                    let token = self.next()?;
+                    let addr = match self.lookup(&token) {
+                        Some(addr) => self.check(addr as i64, 0, 0xFFFF)? as u16,
+                        None if is_name(&token) => {
+                            self.fixup(Fixup::Long, token);
+                            0
+                        }
+                        None => return self.error(format!("invalid address '{}'", token)),
+                    };
+                    self.inst(Inst::LDIL)?;
+                    self.emit_word(addr)
+                }
+                _ => {
+                    let addr = self.addr()?;
+                    self.inst(Inst::LDI(addr))
+                }
+            },
+            "+=" => {
+                let reg = self.expect_register()?;
+                self.inst(Inst::ADDIV(reg))
+            }
+            _ => self.error(format!("invalid operation 'i {}'", op)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.expect_register()?;
+        let op = self.next()?;
+
+        match op.as_str() {
+            "key" => return Ok(Condition { setup: Vec::new(), skip_if_false: Inst::SKNP(x), skip_if_true: Inst::SKP(x) }),
+            "-key" => return Ok(Condition { setup: Vec::new(), skip_if_false: Inst::SKP(x), skip_if_true: Inst::SKNP(x) }),
+            _ => (),
+        }
+
+        let token = self.next()?;
+        let y = self.register(&token);
+        let value = match y {
+            Some(_) => 0,
+            None => self.byte_value(&token)?,
+        };
+        let (equal, not_equal) = match y {
+            Some(y) => (Inst::SEV(x, y), Inst::SNEV(x, y)),
+            None => (Inst::SE(x, value), Inst::SNE(x, value)),
+        };
+
+        match op.as_str() {
+            "==" => return Ok(Condition { setup: Vec::new(), skip_if_false: not_equal, skip_if_true: equal }),
+            "!=" => return Ok(Condition { setup: Vec::new(), skip_if_false: equal, skip_if_true: not_equal }),
+            "<" | ">" | "<=" | ">=" => (),
+            _ => return self.error(format!("unknown comparison '{}'", op)),
+        }
+
+        // Comparisons subtract the operands in VF and look at the flag left there, which is 1 when there
+        // was no borrow
+        let vf = u4::new(0xF);
+        if x == vf || y == Some(vf) {
+            return self.error("vf cannot be used in comparisons".to_string());
+        }
+        let x_minus_y = matches!(op.as_str(), "<" | ">=");
+        let setup = match y {
+            Some(y) => vec![Inst::LDV(vf, x), if x_minus_y { Inst::SUB(vf, y) } else { Inst::SUBN(vf, y) }],
+            None => vec![Inst::LD(vf, value), if x_minus_y { Inst::SUBN(vf, x) } else { Inst::SUB(vf, x) }],
+        };
+
+        // With the subtractions above the flag is 1 exactly when `>=` and `<=` are true
+        let flag_set = Inst::SE(vf, 1);
+        let flag_clear = Inst::SE(vf, 0);
+        let (skip_if_false, skip_if_true) = match op.as_str() {
+            "<=" | ">=" => (flag_clear, flag_set),
+            _ => (flag_set, flag_clear),
+        };
+
+        Ok(Condition { setup, skip_if_false, skip_if_true })
+    }
+
+    /// Read a `{ ... }` block
+    fn block(&mut self) -> Result<Vec<Token>, AsmError> {
+        self.expect("{")?;
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let Some(token) = self.tokens.pop_front() else {
+                return self.error("'{' without a matching '}'".to_string());
+            };
+            self.line = token.line;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 {
+                return Ok(body);
+            }
+            body.push(token);
+        }
+    }
+
+    fn expand(&mut self, name: &str) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return self.error(format!("too many expansions of macro '{}'", name));
+        }
+
+        let param_count = self.macros[name].params.len();
+        let mut args = HashMap::new();
+        for i in 0..param_count {
+            let arg = self.next()?;
+            args.insert(self.macros[name].params[i].clone(), arg);
+        }
+
+        // Expanded tokens keep the line of the invocation, to report errors somewhere useful
+        let line = self.line;
+        for token in self.macros[name].body.iter().rev() {
+            let text = args.get(&token.text).unwrap_or(&token.text).clone();
+            self.tokens.push_front(Token { text, line });
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, AsmError> {
+        let name = self.next()?;
+        if !is_name(&name) || self.register(&name).is_some() {
+            return self.error(format!("invalid name '{}'", name));
+        }
+        Ok(name)
+    }
+
+    fn define_constant(&mut self, name: String, value: f64) -> Result<(), AsmError> {
+        if self.constants.contains_key(&name) {
+            return self.error(format!("constant '{}' is already defined", name));
+        }
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    fn register(&self, token: &str) -> Option<u4> {
+        if let Some(&reg) = self.aliases.get(token) {
+            return Some(reg);
+        }
+        let mut chars = token.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|reg| u4::new(reg as u8)),
+            _ => None,
+        }
+    }
+
+    fn expect_register(&mut self) -> Result<u4, AsmError> {
+        let token = self.next()?;
+        match self.register(&token) {
+            Some(reg) => Ok(reg),
+            None => self.error(format!("expected a register, found '{}'", token)),
+        }
+    }
+
+    /// Value of a number, constant or label
+    fn lookup(&self, token: &str) -> Option<f64> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|&addr| addr as f64))
+    }
+
+    fn constant(&self, token: &str) -> Result<f64, AsmError> {
+        match self.lookup(token) {
+            Some(value) => Ok(value),
+            None => self.error(format!("unknown value '{}'", token)),
+        }
+    }
+
+    fn check(&self, value: i64, min: i64, max: i64) -> Result<i64, AsmError> {
+        if value < min || value > max {
+            return self.error(format!("value {} out of range, must be between {} and {}", value, min, max));
+        }
+        Ok(value)
+    }
+
+    fn byte_value(&self, token: &str) -> Result<u8, AsmError> {
+        let value = self.constant(token)?;
+        Ok(self.check(value.floor() as i64, -0x80, 0xFF)? as u8)
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.byte_value(&token)
+    }
+
+    fn nibble(&mut self) -> Result<u4, AsmError> {
+        let token = self.next()?;
+        let value = self.constant(&token)?;
+        Ok(u4::new(self.check(value.floor() as i64, 0, 0xF)? as u8))
+    }
+
+    /// A value, or a `{ ... }` expression
+    fn value_or_calc(&mut self) -> Result<i64, AsmError> {
+        let value = if self.peek() == Some("{") {
+            self.calc()?
+        } else {
+            let token = self.next()?;
+            self.constant(&token)?
+        };
+        Ok(value.floor() as i64)
+    }
+
+    /// A 12-bit address, possibly referencing a label defined later on
+    fn addr(&mut self) -> Result<u12, AsmError> {
+        let token = self.next()?;
+        self.resolve_addr(token)
+    }
+
+    fn resolve_addr(&mut self, token: String) -> Result<u12, AsmError> {
+        match self.lookup(&token) {
+            Some(addr) => Ok(u12::new(self.check(addr.floor() as i64, 0, 0xFFF)? as u16)),
+            None if is_name(&token) => {
+                self.fixup(Fixup::Addr, token);
+                Ok(u12::new(0))
+            }
+            None => self.error(format!("invalid address '{}'", token)),
+        }
+    }
+
+    /// Record that the instruction about to be written references `label`
+    fn fixup(&mut self, kind: Fixup, label: String) {
+        let pos = match kind {
+            Fixup::Long => self.here + 2,
+            _ => self.here,
+        };
+        self.fixups.push((pos, kind, label, self.line));
+    }
+
+    /// Evaluate a `{ ... }` expression.
+    ///
+    /// As in Octo, binary operators have no precedence and are evaluated right to left, so
+    /// `2 * 3 + 1` is 8. Parentheses group subexpressions and `HERE` is the current address
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        let tokens = self.block()?;
+        let mut pos = 0;
+        let value = self.expr(&tokens, &mut pos)?;
+        if pos != tokens.len() {
+            return self.error(format!("unexpected '{}' in expression", tokens[pos].text));
+        }
+        Ok(value)
+    }
+
+    fn expr(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, AsmError> {
+        let lhs = self.term(tokens, pos)?;
+        let Some(op) = tokens.get(*pos).map(|token| token.text.as_str()) else {
+            return Ok(lhs);
+        };
+        if op == ")" {
+            return Ok(lhs);
+        }
+        *pos += 1;
+        let rhs = self.expr(tokens, pos)?;
+
+        let bool_value = |cond: bool| if cond { 1.0 } else { 0.0 };
+        let value = match op {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" if rhs == 0.0 => return self.error("division by zero".to_string()),
+            "/" => lhs / rhs,
+            "%" if rhs == 0.0 => return self.error("division by zero".to_string()),
+            "%" => lhs % rhs,
+            "&" => (lhs as i64 & rhs as i64) as f64,
+            "|" => (lhs as i64 | rhs as i64) as f64,
+            "^" => (lhs as i64 ^ rhs as i64) as f64,
+            "<<" => ((lhs as i64) << (rhs as i64 & 63)) as f64,
+            ">>" => ((lhs as i64) >> (rhs as i64 & 63)) as f64,
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => bool_value(lhs < rhs),
+            ">" => bool_value(lhs > rhs),
+            "<=" => bool_value(lhs <= rhs),
+            ">=" => bool_value(lhs >= rhs),
+            "==" => bool_value(lhs == rhs),
+            "!=" => bool_value(lhs != rhs),
+            _ => return self.error(format!("unknown operator '{}'", op)),
+        };
+        Ok(value)
+    }
+
+    fn term(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, AsmError> {
+        let Some(token) = tokens.get(*pos) else {
+            return self.error("missing value in expression".to_string());
+        };
+        *pos += 1;
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expr(tokens, pos)?;
+                if tokens.get(*pos).map(|token| token.text.as_str()) != Some(")") {
+                    return self.error("'(' without a matching ')'".to_string());
+                }
+                *pos += 1;
+                Ok(value)
+            }
+            "-" => Ok(-self.term(tokens, pos)?),
+            "~" => Ok(!(self.term(tokens, pos)? as i64) as f64),
+            "!" => Ok(if self.term(tokens, pos)? == 0.0 { 1.0 } else { 0.0 }),
+            "floor" => Ok(self.term(tokens, pos)?.floor()),
+            "HERE" => Ok(self.here as f64),
+            text => self.constant(text),
+        }
+    }
+
+    fn inst(&mut self, inst: Inst) -> Result<(), AsmError> {
+        self.emit_word(inst.encode())
+    }
+
+    /// Emit a jump to be patched later, returning its position
+    fn placeholder_jump(&mut self) -> Result<usize, AsmError> {
+        let pos = self.here;
+        self.inst(Inst::JP(u12::new(0)))?;
+        Ok(pos)
+    }
+
+    fn patch_jump(&mut self, pos: usize, target: usize) -> Result<(), AsmError> {
+        let target = self.check(target as i64, 0, 0xFFF)? as u16;
+        let opcode = Inst::JP(u12::new(target)).encode().to_be_bytes();
+        self.rom[pos - ORIGIN..pos - ORIGIN + 2].copy_from_slice(&opcode);
+        Ok(())
+    }
+
+    fn emit_word(&mut self, word: u16) -> Result<(), AsmError> {
+        let [high, low] = word.to_be_bytes();
+        self.emit(high)?;
+        self.emit(low)
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), AsmError> {
+        if self.here > 0xFFFF {
+            return self.error("program doesn't fit in memory".to_string());
+        }
+        let offset = self.here - ORIGIN;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+            self.written.resize(offset + 1, false);
+        }
+        if self.written[offset] {
+            return self.error(format!("data overlaps at address 0x{:X}", self.here));
+        }
+        self.rom[offset] = byte;
+        self.written[offset] = true;
+        self.here += 1;
+        Ok(())
+    }
+
+    /// Patch all the references to labels, now that they are all known
+    fn resolve(mut self) -> Result<Vec<u8>, AsmError> {
+        for (pos, kind, label, line) in std::mem::take(&mut self.fixups) {
+            self.line = line;
+            let Some(&addr) = self.labels.get(&label) else {
+                return self.error(format!("unknown label '{}'", label));
+            };
+            let offset = pos - ORIGIN;
+            match kind {
+                Fixup::Addr => {
+                    let addr = self.check(addr as i64, 0, 0xFFF)? as u16;
+                    let opcode = u16::from_be_bytes([self.rom[offset], self.rom[offset + 1]]) | addr;
+                    self.rom[offset..offset + 2].copy_from_slice(&opcode.to_be_bytes());
+                }
+                Fixup::Long => {
+                    self.rom[offset..offset + 2].copy_from_slice(&(addr as u16).to_be_bytes());
+                }
+                Fixup::Unpack(nibble) => {
+                    let addr = self.check(addr as i64, 0, 0xFFF)? as u16;
+                    self.rom[offset + 1] = (nibble << 4) | (addr >> 8) as u8;
+                    self.rom[offset + 3] = addr as u8;
+                }
+            }
+        }
+
+        Ok(self.rom)
+    }
+}
+
+fn parse_number(token: &str) -> Option<f64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value } as f64)
+}
+
+/// Names can contain anything but whitespace, as long as they don't look like numbers or directives
+fn is_name(token: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "clear", "return", "exit", "hires", "lores", "scroll-right", "scroll-left", "scroll-up", "scroll-down",
+        "audio", "plane", "jump", "jump0", "native", "sprite", "bcd", "save", "load", "saveflags", "loadflags",
+        "delay", "buzzer", "pitch", "i", "if", "then", "begin", "else", "end", "loop", "while", "again", "key",
+        "-key", "random", "hex", "bighex", "long",
+    ];
+    !token.is_empty()
+        && !token.starts_with(|c: char| c.is_ascii_digit() || c == ':' || c == '-')
+        && !token.chars().any(|c| "{}()=;#".contains(c))
+        && !KEYWORDS.contains(&token)
+}
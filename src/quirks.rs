@@ -0,0 +1,99 @@
+/// Behaviour of the instructions whose semantics differ between CHIP-8 interpreters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY and store the result in VX, instead of shifting VX in place
+    pub shift_uses_vy: bool,
+
+    /// FX55/FX65 leave I pointing past the last register stored/loaded
+    pub load_store_increments_i: bool,
+
+    /// Along with `load_store_increments_i`, FX55/FX65 increment I by X instead of X + 1, leaving it on the
+    /// last register stored/loaded
+    pub load_store_increments_i_by_x: bool,
+
+    /// BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+
+    /// Sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+
+    /// DXYN waits for the next frame before drawing, limiting draws to one per frame
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// Behaviour of this emulator before quirks were configurable, matching no interpreter in particular
+    /// but what many ROMs written for other emulators expect. This is the default
+    pub const fn legacy() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// Original CHIP-8 interpreter for the RCA COSMAC VIP
+    pub const fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            vf_reset: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 for the HP-48 calculators
+    pub const fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: true,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub const fn schip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo
+    pub const fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::legacy()
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+
+/// Ring buffer of save states, used to step the emulation backwards in time.
+///
+/// Only the most recent state is kept in full, older ones are stored as run-length encoded XOR deltas
+/// against the state that follows them, which are tiny since little changes from one frame to the next
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    /// Create a buffer holding up to `capacity` states
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of states currently stored
+    pub fn len(&self) -> usize {
+        self.latest.is_some() as usize + self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Record a new state, dropping the oldest one if the buffer is full
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &latest));
+        }
+        self.latest = Some(state);
+
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Remove and return the most recent state
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        self.latest = self.deltas.pop_back().map(|delta| apply_delta(&latest, &delta));
+        Some(latest)
+    }
+}
+
+/// Encode `target` as a delta against `base`: the length of `target` followed by runs of
+/// (unchanged bytes, changed bytes, XOR of the changed bytes)
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_varint(&mut out, target.len());
+
+    let xor: Vec<u8> = (0..target.len())
+        .map(|i| target[i] ^ base.get(i).copied().unwrap_or(0))
+        .collect();
+
+    let mut pos = 0;
+    while pos < xor.len() {
+        let unchanged = xor[pos..].iter().take_while(|&&byte| byte == 0).count();
+        let changed = xor[pos + unchanged..].iter().take_while(|&&byte| byte != 0).count();
+        write_varint(&mut out, unchanged);
+        write_varint(&mut out, changed);
+        out.extend_from_slice(&xor[pos + unchanged..pos + unchanged + changed]);
+        pos += unchanged + changed;
+    }
+
+    out
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut delta = delta;
+    let len = read_varint(&mut delta);
+
+    let mut out: Vec<u8> = (0..len).map(|i| base.get(i).copied().unwrap_or(0)).collect();
+    let mut pos = 0;
+    while !delta.is_empty() {
+        pos += read_varint(&mut delta);
+        let changed = read_varint(&mut delta);
+        for (byte, xor) in out[pos..pos + changed].iter_mut().zip(&delta[..changed]) {
+            *byte ^= xor;
+        }
+        delta = &delta[changed..];
+        pos += changed;
+    }
+
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[0];
+        *data = &data[1..];
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,30 @@
+/// Deterministic random number generator for the RND instruction, based on SplitMix64.
+///
+/// Its whole state is a single `u64`, which makes runs reproducible from a seed and easy to snapshot
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub(crate) fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+
+    pub(crate) fn next_u8(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        (z >> 56) as u8
+    }
+}
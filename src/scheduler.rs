@@ -0,0 +1,231 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Frames per second of the timers and the display
+pub const FRAME_RATE: u32 = 60;
+
+/// Duration of a frame at full speed
+pub(crate) const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE as u64);
+
+/// Margin left to `thread::sleep`, which can oversleep by a fraction of a millisecond
+const SPIN_MARGIN: Duration = Duration::from_micros(500);
+
+/// Shortest time between two presented frames, a bit less than a frame to allow for jitter
+const PRESENT_INTERVAL: Duration = Duration::from_micros(14_500);
+
+/// Frames the emulator may fall behind by and still catch up, beyond that the missed frames are dropped
+const MAX_LAG: u32 = 4;
+
+/// Emulation speed measured over the last second
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    /// Frames emulated per second, 60 at full speed
+    pub fps: f64,
+
+    /// Frames presented per second, lower than `fps` when some were skipped to catch up
+    pub presented_fps: f64,
+
+    /// Instructions executed per second
+    pub ips: f64,
+
+    /// Frames given up on since the start because the emulator was too far behind
+    pub dropped_frames: u64,
+}
+
+impl FrameStats {
+    /// Speed relative to the original hardware, 1.0 at full speed
+    pub fn speed(&self) -> f64 {
+        self.fps / FRAME_RATE as f64
+    }
+}
+
+/// Paces the emulation in frames of 1/60th of a second, each running `freq / 60` instructions.
+///
+/// Frame deadlines are computed from a fixed starting point so rounding errors don't accumulate. Frames are
+/// presented at most at the display rate, so when the emulator falls behind or runs faster than real time
+/// some frames are run back to back without being presented. When it is too far behind to catch up the
+/// missed frames are dropped instead
+pub(crate) struct Scheduler {
+    freq: usize,
+    /// Multiplier of the frame rate, 0 to run frames as fast as possible
+    speed: f64,
+    start: Instant,
+    frames: u64,
+    /// Instructions owed to the next frames, in 1/60th of an instruction, when `freq` isn't a multiple of 60
+    cycle_remainder: usize,
+    last_present: Option<Instant>,
+    stats: FrameStats,
+    window_start: Instant,
+    window_frames: u64,
+    window_presented: u64,
+    window_instructions: u64,
+}
+
+impl Scheduler {
+    pub fn new(freq: usize) -> Scheduler {
+        let now = Instant::now();
+        Scheduler {
+            freq,
+            speed: 1.0,
+            start: now,
+            frames: 0,
+            cycle_remainder: 0,
+            last_present: None,
+            stats: FrameStats::default(),
+            window_start: now,
+            window_frames: 0,
+            window_presented: 0,
+            window_instructions: 0,
+        }
+    }
+
+    /// Start counting frames from now, e.g. after being paused, and measure the speed anew
+    pub fn reset(&mut self) {
+        let now = Instant::now();
+        self.start = now;
+        self.frames = 0;
+        self.stats = FrameStats { dropped_frames: self.stats.dropped_frames, ..FrameStats::default() };
+        self.window_start = now;
+        self.window_frames = 0;
+        self.window_presented = 0;
+        self.window_instructions = 0;
+    }
+
+    /// Run frames `speed` times faster than real time, 0 runs them as fast as possible
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+        self.reset();
+    }
+
+    pub fn freq(&self) -> usize {
+        self.freq
+    }
+
+    /// Execute `freq` instructions per second, 0 runs them as fast as possible
+    pub fn set_freq(&mut self, freq: usize) {
+        self.freq = freq;
+        self.cycle_remainder = 0;
+        self.reset();
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    /// Start a frame, giving the instructions to execute in it
+    pub fn begin_frame(&mut self) -> FrameBudget {
+        FrameBudget {
+            cycles: self.frame_cycles(),
+            end: self.frame_end(),
+            executed: 0,
+        }
+    }
+
+    /// Instructions to execute in the next frame, None when running as fast as possible
+    fn frame_cycles(&mut self) -> Option<usize> {
+        if self.freq == 0 {
+            return None;
+        }
+        let owed = self.cycle_remainder + self.freq;
+        self.cycle_remainder = owed % FRAME_RATE as usize;
+        Some(owed / FRAME_RATE as usize)
+    }
+
+    /// When to stop executing instructions in a frame without a fixed number of them
+    fn frame_end(&self) -> Instant {
+        if self.speed == 0.0 {
+            Instant::now() + FRAME_DURATION
+        } else {
+            self.deadline()
+        }
+    }
+
+    /// When the current frame ends
+    fn deadline(&self) -> Instant {
+        if self.speed == 0.0 {
+            return self.start;
+        }
+        self.start + self.frame_duration().mul_f64((self.frames + 1) as f64)
+    }
+
+    fn frame_duration(&self) -> Duration {
+        FRAME_DURATION.div_f64(self.speed)
+    }
+
+    /// Whether the current frame should be presented, which is skipped when the previous one was
+    /// presented less than a display frame ago
+    pub fn should_present(&self) -> bool {
+        self.last_present.is_none_or(|last_present| last_present.elapsed() >= PRESENT_INTERVAL)
+    }
+
+    /// Account for a finished frame and return when the next one should start, which is in the past when
+    /// behind
+    pub fn end_frame(&mut self, instructions: usize, presented: bool) -> Instant {
+        let deadline = self.deadline();
+        let now = Instant::now();
+        self.frames += 1;
+        if presented {
+            self.last_present = Some(now);
+        }
+        self.window_frames += 1;
+        self.window_presented += presented as u64;
+        self.window_instructions += instructions as u64;
+
+        let lag = now.saturating_duration_since(deadline);
+        if self.speed != 0.0 && lag > self.frame_duration() * MAX_LAG {
+            // Too far behind to catch up, e.g. after the process was suspended, carry on from now
+            self.stats.dropped_frames += lag.div_duration_f64(self.frame_duration()) as u64;
+            self.start = now;
+            self.frames = 0;
+        }
+
+        let elapsed = now.duration_since(self.window_start).as_secs_f64();
+        if elapsed >= 1.0 {
+            self.stats.fps = self.window_frames as f64 / elapsed;
+            self.stats.presented_fps = self.window_presented as f64 / elapsed;
+            self.stats.ips = self.window_instructions as f64 / elapsed;
+            self.window_start = now;
+            self.window_frames = 0;
+            self.window_presented = 0;
+            self.window_instructions = 0;
+        }
+
+        deadline
+    }
+
+    /// Leave `duration` out of the timing, e.g. while stopped in the debugger
+    pub fn skip(&mut self, duration: Duration) {
+        self.start += duration;
+        self.window_start += duration;
+    }
+
+    /// Whether the statistics were refreshed by the last `end_frame`
+    pub fn stats_updated(&self) -> bool {
+        self.window_frames == 0
+    }
+}
+
+/// Instructions left to execute in a frame, either a fixed number of them or as many as fit until its end
+pub(crate) struct FrameBudget {
+    cycles: Option<usize>,
+    end: Instant,
+    pub executed: usize,
+}
+
+impl FrameBudget {
+    /// Whether another instruction should be executed in the frame
+    pub fn has_cycles(&self) -> bool {
+        self.cycles.map_or(Instant::now() < self.end, |cycles| self.executed < cycles)
+    }
+}
+
+/// Sleep until `deadline`, sleeping most of the time and yielding for the last moment to wake up on time
+pub(crate) fn sleep_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now + SPIN_MARGIN {
+        thread::sleep(deadline - now - SPIN_MARGIN);
+    }
+    while Instant::now() < deadline {
+        thread::yield_now();
+    }
+}
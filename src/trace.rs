@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::chip8::Registers;
+use crate::error::Chip8Error;
+use crate::instructions::Inst;
+
+/// Log of the executed instructions, one per line.
+///
+/// Every line holds the cycle number, the address and opcode of the instruction, its disassembly and what it
+/// changed, for example:
+///
+/// ```text
+/// 00000042 0x21A 0xD015 DRW V0, V1, 5 VF=00
+/// 00000043 0x21C 0x2300 CALL 0x300 SP=01 S0=21E
+/// 00000044 0x300 0xF233 LD B, V2 [0x3F0]=01 [0x3F1]=02 [0x3F2]=05
+/// ```
+///
+/// Changed registers come in a fixed order (V0-VF, I, SP, DT, ST, then the stack entries and memory bytes by
+/// address), so traces of two runs can be compared with `diff`. The PC is left out, as it is on the next line
+pub struct Tracer {
+    out: Box<dyn Write>,
+    addrs: Option<RangeInclusive<u16>>,
+    mnemonics: Option<Vec<String>>,
+    /// Last lines kept in memory when only the instructions leading to a fault are wanted
+    last: Option<(usize, VecDeque<String>)>,
+    cycle: u64,
+}
+
+/// Machine state before an instruction, to find out what it changed
+pub(crate) struct Snapshot {
+    pub pc: u16,
+    pub opcode: u16,
+    pub reg: Registers,
+    pub stack: [u16; 16],
+    /// Address and contents of the memory the instruction may write
+    pub memory: Option<(usize, Vec<u8>)>,
+}
+
+impl Tracer {
+    /// Trace every instruction to `out`
+    pub fn new(out: Box<dyn Write>) -> Tracer {
+        Tracer {
+            out,
+            addrs: None,
+            mnemonics: None,
+            last: None,
+            cycle: 0,
+        }
+    }
+
+    /// Only trace the instructions at the given addresses
+    pub fn set_addrs(&mut self, addrs: RangeInclusive<u16>) {
+        self.addrs = Some(addrs);
+    }
+
+    /// Only trace the instructions with the given mnemonics, such as `DRW` or `CALL`. `LD` covers all its forms
+    pub fn set_mnemonics(&mut self, mnemonics: &[String]) {
+        self.mnemonics = Some(mnemonics.iter().map(|mnemonic| mnemonic.to_ascii_uppercase()).collect());
+    }
+
+    /// Keep the last `len` instructions in memory instead, and only write them out when a fault occurs
+    pub fn set_last(&mut self, len: usize) {
+        self.last = Some((len, VecDeque::with_capacity(len)));
+    }
+
+    /// Number of instructions executed so far
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Record an instruction, given the state before it and the state after it
+    pub(crate) fn record(
+        &mut self,
+        before: &Snapshot,
+        reg: &Registers,
+        stack: &[u16; 16],
+        memory: &[u8],
+        result: &Result<(), Chip8Error>,
+    ) -> io::Result<()> {
+        let cycle = self.cycle;
+        self.cycle += 1;
+
+        if let Err(err) = result {
+            self.dump()?;
+            writeln!(self.out, "{:08} 0x{:03X} 0x{:04X} fault: {}", cycle, before.pc, before.opcode, err)?;
+            return self.out.flush();
+        }
+
+        if !self.addrs.as_ref().is_none_or(|addrs| addrs.contains(&before.pc)) {
+            return Ok(());
+        }
+        let disassembly = Inst::decode(before.opcode).map_or_else(|| "???".to_string(), |inst| inst.to_string());
+        if let Some(ref mnemonics) = self.mnemonics {
+            let mnemonic = disassembly.split(' ').next().unwrap_or_default();
+            if !mnemonics.iter().any(|wanted| wanted == mnemonic) {
+                return Ok(());
+            }
+        }
+
+        let mut line = format!("{:08} 0x{:03X} 0x{:04X} {}", cycle, before.pc, before.opcode, disassembly);
+        for (x, (old, new)) in before.reg.v.iter().zip(reg.v).enumerate() {
+            if *old != new {
+                write!(line, " V{:X}={:02X}", x, new).unwrap();
+            }
+        }
+        if before.reg.i != reg.i {
+            write!(line, " I={:03X}", reg.i).unwrap();
+        }
+        if before.reg.sp != reg.sp {
+            write!(line, " SP={:02X}", reg.sp).unwrap();
+        }
+        if before.reg.dt != reg.dt {
+            write!(line, " DT={:02X}", reg.dt).unwrap();
+        }
+        if before.reg.st != reg.st {
+            write!(line, " ST={:02X}", reg.st).unwrap();
+        }
+        for (x, (old, new)) in before.stack.iter().zip(stack).enumerate() {
+            if old != new {
+                write!(line, " S{:X}={:03X}", x, new).unwrap();
+            }
+        }
+        if let Some((start, ref old)) = before.memory {
+            for (offset, (old, new)) in old.iter().zip(&memory[start..start + old.len()]).enumerate() {
+                if old != new {
+                    write!(line, " [0x{:03X}]={:02X}", start + offset, new).unwrap();
+                }
+            }
+        }
+        self.push(line)
+    }
+
+    fn push(&mut self, line: String) -> io::Result<()> {
+        match self.last {
+            Some((len, ref mut lines)) => {
+                if lines.len() == len {
+                    lines.pop_front();
+                }
+                if len > 0 {
+                    lines.push_back(line);
+                }
+                Ok(())
+            }
+            None => writeln!(self.out, "{}", line),
+        }
+    }
+
+    /// Write out the instructions kept in memory
+    fn dump(&mut self) -> io::Result<()> {
+        if let Some((_, ref mut lines)) = self.last {
+            for line in lines.drain(..) {
+                writeln!(self.out, "{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::Path;
+
+use chip8::Inst;
+use chip8::asm::assemble;
+use chip8::disasm::disassemble_source;
+
+#[test]
+fn encode_is_inverse_of_decode() {
+    for opcode in 0..=0xFFFF {
+        if let Some(inst) = Inst::decode(opcode) {
+            assert_eq!(inst.encode(), opcode, "{}", inst);
+        }
+    }
+}
+
+#[test]
+fn assemble_disassembled_instructions() {
+    for opcode in 0..=0xFFFF {
+        if let Some(inst) = Inst::decode(opcode) {
+            let mut source = inst.to_string();
+            let mut expected = opcode.to_be_bytes().to_vec();
+            if inst == Inst::LDIL {
+                source += " 0x1234";
+                expected.extend_from_slice(&[0x12, 0x34]);
+            }
+            assert_eq!(assemble(&source, 0x200).unwrap(), expected, "{}", source);
+        }
+    }
+}
+
+#[test]
+fn labels_constants_and_data() {
+    let source = "
+        SPEED = 3
+        start:
+            LD V0, SPEED + 1   ; comment
+            LD I, sprite
+            DRW V0, V0, sprite_end - sprite
+            JP start
+        sprite: db 0xF0, 0b10010000, 0x90
+        sprite_end:
+            dw 0x1234
+    ";
+    let rom = assemble(source, 0x200).unwrap();
+    assert_eq!(rom, [0x60, 0x04, 0xA2, 0x08, 0xD0, 0x03, 0x12, 0x00, 0xF0, 0x90, 0x90, 0x12, 0x34]);
+}
+
+#[test]
+fn errors_report_line_numbers() {
+    let err = assemble("CLS\nLD V0, 256\n", 0x200).unwrap_err();
+    assert_eq!(err.line, 2);
+
+    let err = assemble("CLS\n\nJP nowhere\n", 0x200).unwrap_err();
+    assert_eq!(err.line, 3);
+    assert!(err.message.contains("nowhere"));
+
+    let err = assemble("FOO V0\n", 0x200).unwrap_err();
+    assert_eq!(err.line, 1);
+
+    let err = assemble("a:\na:\n", 0x200).unwrap_err();
+    assert_eq!(err.line, 2);
+}
+
+#[test]
+fn reassemble_disassembled_roms() {
+    // Every ROM shipped with the repo disassembles to source that assembles back to the same bytes
+    let mut pending = vec![Path::new(env!("CARGO_MANIFEST_DIR")).join("roms")];
+    let mut count = 0;
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "ch8") {
+                let rom = fs::read(&path).unwrap();
+                let source = disassemble_source(&rom, 0x200);
+                let assembled = assemble(&source, 0x200).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+                assert!(assembled == rom, "{} doesn't reassemble to the same bytes", path.display());
+                count += 1;
+            }
+        }
+    }
+    assert!(count > 0);
+}
@@ -0,0 +1,90 @@
+use chip8::debugger::{Breakpoint, Comparison, Condition, Debugger, Register, Watchpoint};
+use chip8::graphics::HeadlessGraphics;
+use chip8::{Chip8, Quirks};
+
+/// Execute instructions like the run loop until the debugger stops, giving up after `limit` of them
+fn continue_until_stop(chip8: &mut Chip8<HeadlessGraphics>, debugger: &mut Debugger, limit: usize) -> Option<String> {
+    debugger.resume();
+    chip8.step().unwrap();
+    for _ in 0..limit {
+        if let Some(reason) = debugger.stop_reason(chip8) {
+            return Some(reason);
+        }
+        chip8.step().unwrap();
+    }
+    None
+}
+
+#[test]
+fn breakpoint_on_key_wait() {
+    let rom = [
+        0xF3, 0x0A, // LD V3, K
+        0x12, 0x02, // JP 0x202
+    ];
+    let mut chip8 = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &rom).unwrap();
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(Breakpoint { addr: Some(0x200), condition: None });
+    debugger.add_breakpoint(Breakpoint { addr: Some(0x202), condition: None });
+    debugger.resume();
+    assert_eq!(debugger.stop_reason(&chip8).as_deref(), Some("Breakpoint 1 at 0x200"));
+
+    // Continuing waits for the key without stopping again at FX0A, which runs again and again meanwhile
+    assert_eq!(continue_until_stop(&mut chip8, &mut debugger, 10), None);
+    chip8.graphics_mut().press_key(0x7);
+    assert_eq!(continue_until_stop(&mut chip8, &mut debugger, 10), None);
+    assert_eq!(chip8.registers().pc, 0x200);
+
+    chip8.graphics_mut().release_key(0x7);
+    assert_eq!(continue_until_stop(&mut chip8, &mut debugger, 10).as_deref(), Some("Breakpoint 2 at 0x202"));
+    assert_eq!(chip8.registers().v[3], 0x7);
+
+    // A jump to itself completes every time, so it stops every time
+    assert_eq!(continue_until_stop(&mut chip8, &mut debugger, 10).as_deref(), Some("Breakpoint 2 at 0x202"));
+}
+
+#[test]
+fn watchpoint_on_display_wait() {
+    let rom = [
+        0xD0, 0x05, // DRW V0, V0, 5
+        0xD0, 0x05, // DRW V0, V0, 5
+        0x12, 0x04, // JP 0x204
+    ];
+    let mut chip8 = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &rom).unwrap();
+    let mut debugger = Debugger::new();
+    debugger.add_watchpoint(Watchpoint { addrs: 0..=0, read: true, write: false });
+    debugger.resume();
+    assert_eq!(debugger.stop_reason(&chip8).as_deref(), Some("Watchpoint 1: read of 0x000-0x004 at 0x200"));
+    assert_eq!(
+        continue_until_stop(&mut chip8, &mut debugger, 10).as_deref(),
+        Some("Watchpoint 1: read of 0x000-0x004 at 0x202")
+    );
+
+    // The second sprite waits for the next frame without stopping again, and is drawn once it starts
+    assert_eq!(continue_until_stop(&mut chip8, &mut debugger, 10), None);
+    assert_eq!(chip8.registers().pc, 0x202);
+    chip8.tick_timers();
+    assert_eq!(continue_until_stop(&mut chip8, &mut debugger, 10), None);
+    assert_eq!(chip8.registers().pc, 0x204);
+}
+
+#[test]
+fn condition_without_address() {
+    let rom = [
+        0x70, 0x01, // ADD V0, 1
+        0x12, 0x00, // JP 0x200
+    ];
+    let mut chip8 = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &rom).unwrap();
+    let mut debugger = Debugger::new();
+    let condition = Condition { reg: Register::V(0), cmp: Comparison::Ge, value: 2 };
+    debugger.add_breakpoint(Breakpoint { addr: None, condition: Some(condition) });
+    debugger.resume();
+    assert_eq!(debugger.stop_reason(&chip8), None);
+
+    // It stops once the condition starts holding, after the second ADD
+    assert_eq!(continue_until_stop(&mut chip8, &mut debugger, 10).as_deref(), Some("Breakpoint 1 at 0x202"));
+    assert_eq!(chip8.registers().v[0], 2);
+
+    // Continuing runs while it keeps holding, until it stops holding when V0 wraps around and holds again
+    assert_eq!(continue_until_stop(&mut chip8, &mut debugger, 1000).as_deref(), Some("Breakpoint 1 at 0x202"));
+    assert_eq!(chip8.registers().v[0], 2);
+}
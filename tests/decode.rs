@@ -0,0 +1,117 @@
+use arbitrary_int::{u4, u12};
+use chip8::Inst;
+
+/// Operands of an opcode, named as in the usual opcode tables
+struct Fields {
+    x: u4,
+    y: u4,
+    n: u4,
+    kk: u8,
+    nnn: u12,
+}
+
+/// Builds the instruction from the operands of its opcode
+type Constructor = fn(Fields) -> Inst;
+
+/// Opcode patterns of every instruction, where lowercase letters are operands and hex digits must match.
+/// The first matching pattern wins, so the more specific ones come first
+const TABLE: &[(&str, Constructor)] = &[
+    ("00E0", |_| Inst::CLS),
+    ("00EE", |_| Inst::RET),
+    ("00Cn", |f| Inst::SCD(f.n)),
+    ("00Dn", |f| Inst::SCU(f.n)),
+    ("00FB", |_| Inst::SCR),
+    ("00FC", |_| Inst::SCL),
+    ("00FD", |_| Inst::EXIT),
+    ("00FE", |_| Inst::LOW),
+    ("00FF", |_| Inst::HIGH),
+    ("0nnn", |f| Inst::SYS(f.nnn)),
+    ("1nnn", |f| Inst::JP(f.nnn)),
+    ("2nnn", |f| Inst::CALL(f.nnn)),
+    ("3xkk", |f| Inst::SE(f.x, f.kk)),
+    ("4xkk", |f| Inst::SNE(f.x, f.kk)),
+    ("5xy0", |f| Inst::SEV(f.x, f.y)),
+    ("5xy2", |f| Inst::SAVE(f.x, f.y)),
+    ("5xy3", |f| Inst::LOAD(f.x, f.y)),
+    ("6xkk", |f| Inst::LD(f.x, f.kk)),
+    ("7xkk", |f| Inst::ADD(f.x, f.kk)),
+    ("8xy0", |f| Inst::LDV(f.x, f.y)),
+    ("8xy1", |f| Inst::OR(f.x, f.y)),
+    ("8xy2", |f| Inst::AND(f.x, f.y)),
+    ("8xy3", |f| Inst::XOR(f.x, f.y)),
+    ("8xy4", |f| Inst::ADDV(f.x, f.y)),
+    ("8xy5", |f| Inst::SUB(f.x, f.y)),
+    ("8xy6", |f| Inst::SHR(f.x, f.y)),
+    ("8xy7", |f| Inst::SUBN(f.x, f.y)),
+    ("8xyE", |f| Inst::SHL(f.x, f.y)),
+    ("9xy0", |f| Inst::SNEV(f.x, f.y)),
+    ("Annn", |f| Inst::LDI(f.nnn)),
+    ("Bnnn", |f| Inst::JPV(f.nnn)),
+    ("Cxkk", |f| Inst::RND(f.x, f.kk)),
+    ("Dxyn", |f| Inst::DRW(f.x, f.y, f.n)),
+    ("Ex9E", |f| Inst::SKP(f.x)),
+    ("ExA1", |f| Inst::SKNP(f.x)),
+    ("F000", |_| Inst::LDIL),
+    ("F002", |_| Inst::AUDIO),
+    ("Fx01", |f| Inst::PLANE(f.x)),
+    ("Fx07", |f| Inst::LDVDT(f.x)),
+    ("Fx0A", |f| Inst::LDVKEY(f.x)),
+    ("Fx15", |f| Inst::LDDTV(f.x)),
+    ("Fx18", |f| Inst::LDSTV(f.x)),
+    ("Fx1E", |f| Inst::ADDIV(f.x)),
+    ("Fx29", |f| Inst::LDFV(f.x)),
+    ("Fx30", |f| Inst::LDHFV(f.x)),
+    ("Fx33", |f| Inst::LDBV(f.x)),
+    ("Fx3A", |f| Inst::PITCH(f.x)),
+    ("Fx55", |f| Inst::LDIV(f.x)),
+    ("Fx65", |f| Inst::LDVI(f.x)),
+    ("Fx75", |f| Inst::LDRV(f.x)),
+    ("Fx85", |f| Inst::LDVR(f.x)),
+];
+
+fn matches(pattern: &str, opcode: u16) -> bool {
+    pattern.chars().zip((0..4).rev()).all(|(c, nibble)| match c.to_digit(16) {
+        Some(digit) => (opcode >> (nibble * 4)) & 0xF == digit as u16,
+        None => true,
+    })
+}
+
+fn expected(opcode: u16) -> Option<Inst> {
+    let (_, inst) = TABLE.iter().find(|(pattern, _)| matches(pattern, opcode))?;
+    Some(inst(Fields {
+        x: u4::extract_u16(opcode, 8),
+        y: u4::extract_u16(opcode, 4),
+        n: u4::extract_u16(opcode, 0),
+        kk: opcode as u8,
+        nnn: u12::extract_u16(opcode, 0),
+    }))
+}
+
+#[test]
+fn decode_all_opcodes() {
+    for opcode in 0..=u16::MAX {
+        assert_eq!(Inst::decode(opcode), expected(opcode), "decoding {:04X}", opcode);
+    }
+}
+
+#[test]
+fn encode_inverts_decode() {
+    for opcode in 0..=u16::MAX {
+        if let Some(inst) = Inst::decode(opcode) {
+            assert_eq!(inst.encode(), opcode, "encoding {}", inst);
+        }
+    }
+}
+
+#[test]
+fn table_patterns() {
+    // Spot checks of the pattern matching itself, so a broken table can't agree with a broken decoder
+    assert!(matches("8xyE", 0x812E));
+    assert!(!matches("8xyE", 0x812F));
+    assert!(matches("00Cn", 0x00C7));
+    assert!(!matches("F000", 0xF100));
+    assert_eq!(expected(0x00E0), Some(Inst::CLS));
+    assert_eq!(expected(0x5121), None);
+    assert_eq!(expected(0xF102), None);
+    assert_eq!(expected(0xD12F), Some(Inst::DRW(u4::new(1), u4::new(2), u4::new(0xF))));
+}
@@ -0,0 +1,39 @@
+use chip8::disasm::disassemble;
+
+#[test]
+fn labels_and_data() {
+    let rom = [
+        0xA2, 0x06, // LD I, 0x206
+        0xD0, 0x01, // DRW V0, V0, 1
+        0x12, 0x02, // JP 0x202
+        0xF0, // sprite
+    ];
+    let expected = "    0x200  A2 06        LD I, 0x206
+loc_202:
+    0x202  D0 01        DRW V0, V0, 1
+    0x204  12 02        JP loc_202
+    0x206  F0           db 0xF0  ; ####....
+";
+    assert_eq!(disassemble(&rom, 0x200), expected);
+}
+
+#[test]
+fn targets_without_a_line_show_the_address() {
+    let rom = [
+        0x22, 0x08, // CALL 0x208
+        0x30, 0x00, // SE V0, 0
+        0x13, 0x00, // JP 0x300, past the end of the ROM
+        0x12, 0x09, // JP 0x209, in the middle of the next instruction
+        0x60, 0xE0, // LD V0, 0xE0
+        0x00, 0xEE, // RET
+    ];
+    let expected = "    0x200  22 08        CALL sub_208
+    0x202  30 00        SE V0, 0x00
+    0x204  13 00        JP 0x300
+    0x206  12 09        JP 0x209
+sub_208:
+    0x208  60 E0        LD V0, 0xE0
+    0x20A  00 EE        RET
+";
+    assert_eq!(disassemble(&rom, 0x200), expected);
+}
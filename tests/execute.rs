@@ -0,0 +1,666 @@
+use chip8::graphics::HeadlessGraphics;
+use chip8::{Chip8, Chip8Error, Quirks, StateError};
+
+/// Machine with `program` loaded at 0x200
+fn machine(quirks: Quirks, program: &[u16]) -> Chip8<HeadlessGraphics> {
+    let rom: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+    Chip8::with_rom(0, HeadlessGraphics::new(64, 32), quirks, &rom).unwrap()
+}
+
+/// Run `opcode` with the given register values, returning the machine afterwards
+fn exec(quirks: Quirks, opcode: u16, regs: &[(usize, u8)]) -> Chip8<HeadlessGraphics> {
+    let mut chip8 = machine(quirks, &[opcode]);
+    for &(x, val) in regs {
+        chip8.registers_mut().v[x] = val;
+    }
+    chip8.step().unwrap();
+    chip8
+}
+
+fn vip(opcode: u16, regs: &[(usize, u8)]) -> Chip8<HeadlessGraphics> {
+    exec(Quirks::cosmac_vip(), opcode, regs)
+}
+
+fn schip(opcode: u16, regs: &[(usize, u8)]) -> Chip8<HeadlessGraphics> {
+    exec(Quirks::schip(), opcode, regs)
+}
+
+#[test]
+fn ld_and_add_immediate() {
+    assert_eq!(vip(0x6A42, &[]).registers().v[0xA], 0x42);
+
+    // 7XKK wraps around and leaves VF alone
+    let chip8 = vip(0x70FF, &[(0, 2), (0xF, 7)]);
+    assert_eq!(chip8.registers().v[0], 1);
+    assert_eq!(chip8.registers().v[0xF], 7);
+}
+
+#[test]
+fn ldv_copies() {
+    assert_eq!(vip(0x8120, &[(2, 9)]).registers().v[1], 9);
+}
+
+#[test]
+fn logic_ops() {
+    let regs = [(0, 0b1100), (1, 0b1010), (0xF, 5)];
+    assert_eq!(schip(0x8011, &regs).registers().v[0], 0b1110);
+    assert_eq!(schip(0x8012, &regs).registers().v[0], 0b1000);
+    assert_eq!(schip(0x8013, &regs).registers().v[0], 0b0110);
+
+    // VF is only reset on the COSMAC VIP
+    assert_eq!(schip(0x8011, &regs).registers().v[0xF], 5);
+    assert_eq!(vip(0x8011, &regs).registers().v[0xF], 0);
+    assert_eq!(vip(0x8012, &regs).registers().v[0xF], 0);
+    assert_eq!(vip(0x8013, &regs).registers().v[0xF], 0);
+}
+
+#[test]
+fn addv_carry() {
+    let chip8 = vip(0x8014, &[(0, 0x10), (1, 0x20)]);
+    assert_eq!(chip8.registers().v[0], 0x30);
+    assert_eq!(chip8.registers().v[0xF], 0);
+
+    let chip8 = vip(0x8014, &[(0, 0xFF), (1, 0x01)]);
+    assert_eq!(chip8.registers().v[0], 0x00);
+    assert_eq!(chip8.registers().v[0xF], 1);
+
+    let chip8 = vip(0x8014, &[(0, 0xFF), (1, 0xFF)]);
+    assert_eq!(chip8.registers().v[0], 0xFE);
+    assert_eq!(chip8.registers().v[0xF], 1);
+}
+
+#[test]
+fn sub_borrow() {
+    let chip8 = vip(0x8015, &[(0, 5), (1, 3)]);
+    assert_eq!(chip8.registers().v[0], 2);
+    assert_eq!(chip8.registers().v[0xF], 1);
+
+    let chip8 = vip(0x8015, &[(0, 3), (1, 5)]);
+    assert_eq!(chip8.registers().v[0], 0xFE);
+    assert_eq!(chip8.registers().v[0xF], 0);
+
+    // No borrow when both are equal
+    let chip8 = vip(0x8015, &[(0, 5), (1, 5)]);
+    assert_eq!(chip8.registers().v[0], 0);
+    assert_eq!(chip8.registers().v[0xF], 1);
+}
+
+#[test]
+fn subn_borrow() {
+    let chip8 = vip(0x8017, &[(0, 3), (1, 5)]);
+    assert_eq!(chip8.registers().v[0], 2);
+    assert_eq!(chip8.registers().v[0xF], 1);
+
+    let chip8 = vip(0x8017, &[(0, 5), (1, 3)]);
+    assert_eq!(chip8.registers().v[0], 0xFE);
+    assert_eq!(chip8.registers().v[0xF], 0);
+
+    let chip8 = vip(0x8017, &[(0, 5), (1, 5)]);
+    assert_eq!(chip8.registers().v[0], 0);
+    assert_eq!(chip8.registers().v[0xF], 1);
+}
+
+#[test]
+fn shifts() {
+    let chip8 = schip(0x8006, &[(0, 0b0000_0011)]);
+    assert_eq!(chip8.registers().v[0], 0b0000_0001);
+    assert_eq!(chip8.registers().v[0xF], 1);
+
+    let chip8 = schip(0x8006, &[(0, 0b0000_0010)]);
+    assert_eq!(chip8.registers().v[0], 0b0000_0001);
+    assert_eq!(chip8.registers().v[0xF], 0);
+
+    // VF gets the bit shifted out, not its value in place
+    let chip8 = schip(0x800E, &[(0, 0b1000_0001)]);
+    assert_eq!(chip8.registers().v[0], 0b0000_0010);
+    assert_eq!(chip8.registers().v[0xF], 1);
+
+    let chip8 = schip(0x800E, &[(0, 0b0100_0000)]);
+    assert_eq!(chip8.registers().v[0], 0b1000_0000);
+    assert_eq!(chip8.registers().v[0xF], 0);
+}
+
+#[test]
+fn shifts_use_vy_on_vip() {
+    let chip8 = vip(0x8016, &[(0, 0xFF), (1, 0b0000_0100)]);
+    assert_eq!(chip8.registers().v[0], 0b0000_0010);
+    assert_eq!(chip8.registers().v[1], 0b0000_0100);
+    assert_eq!(chip8.registers().v[0xF], 0);
+
+    let chip8 = vip(0x801E, &[(0, 0xFF), (1, 0b1000_0100)]);
+    assert_eq!(chip8.registers().v[0], 0b0000_1000);
+    assert_eq!(chip8.registers().v[0xF], 1);
+}
+
+#[test]
+fn flag_wins_over_result_in_vf() {
+    // When VF is the destination, the flag is written last and replaces the result
+    let chip8 = vip(0x8F14, &[(0xF, 0x80), (1, 0x10)]);
+    assert_eq!(chip8.registers().v[0xF], 0);
+
+    let chip8 = vip(0x8F15, &[(0xF, 5), (1, 3)]);
+    assert_eq!(chip8.registers().v[0xF], 1);
+
+    let chip8 = vip(0x8F17, &[(0xF, 5), (1, 3)]);
+    assert_eq!(chip8.registers().v[0xF], 0);
+
+    let chip8 = schip(0x8F06, &[(0xF, 0b0000_0010)]);
+    assert_eq!(chip8.registers().v[0xF], 0);
+
+    let chip8 = schip(0x8F0E, &[(0xF, 0b1000_0000)]);
+    assert_eq!(chip8.registers().v[0xF], 1);
+}
+
+#[test]
+fn vf_as_source_operand() {
+    // The flag is computed from VF's value before it gets overwritten
+    let chip8 = vip(0x80F4, &[(0, 0xFF), (0xF, 0x01)]);
+    assert_eq!(chip8.registers().v[0], 0x00);
+    assert_eq!(chip8.registers().v[0xF], 1);
+
+    let chip8 = vip(0x80F5, &[(0, 1), (0xF, 2)]);
+    assert_eq!(chip8.registers().v[0], 0xFF);
+    assert_eq!(chip8.registers().v[0xF], 0);
+}
+
+#[test]
+fn skips() {
+    let regs = [(0, 7), (1, 7), (2, 8)];
+    let pc = |opcode| vip(opcode, &regs).registers().pc;
+    assert_eq!(pc(0x3007), 0x204);
+    assert_eq!(pc(0x3008), 0x202);
+    assert_eq!(pc(0x4007), 0x202);
+    assert_eq!(pc(0x4008), 0x204);
+    assert_eq!(pc(0x5010), 0x204);
+    assert_eq!(pc(0x5020), 0x202);
+    assert_eq!(pc(0x9010), 0x202);
+    assert_eq!(pc(0x9020), 0x204);
+}
+
+#[test]
+fn skips_on_keys() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xE09E, 0x0000, 0xE0A1]);
+    chip8.registers_mut().v[0] = 0xA;
+    chip8.graphics_mut().press_key(0xA);
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().pc, 0x204);
+
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().pc, 0x206);
+}
+
+#[test]
+fn key_wait_completes_on_release() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF30A]);
+    chip8.registers_mut().dt = 10;
+
+    // Nothing happens until a key is pressed and released, while the timers keep running
+    chip8.run_frame(4).unwrap();
+    assert_eq!(chip8.registers().pc, 0x200);
+    assert_eq!(chip8.registers().dt, 9);
+
+    chip8.graphics_mut().press_key(0x7);
+    chip8.run_frame(4).unwrap();
+    assert_eq!(chip8.registers().pc, 0x200);
+
+    chip8.graphics_mut().release_key(0x7);
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().pc, 0x202);
+    assert_eq!(chip8.registers().v[3], 0x7);
+}
+
+#[test]
+fn key_wait_survives_save_states() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF30A]);
+    chip8.graphics_mut().press_key(0x7);
+    chip8.step().unwrap();
+    let state = chip8.save_state();
+
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF30A]);
+    chip8.load_state(&state).unwrap();
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().pc, 0x202);
+    assert_eq!(chip8.registers().v[3], 0x7);
+}
+
+#[test]
+fn save_states_are_reproducible() {
+    // The state only depends on the machine, not on when it was saved
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0x6005, 0xF015]);
+    chip8.run_frame(2).unwrap();
+    let state = chip8.save_state();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    assert_eq!(chip8.save_state(), state);
+}
+
+#[test]
+fn corrupted_save_states_are_rejected() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF30A]);
+    chip8.graphics_mut().press_key(0x7);
+    chip8.step().unwrap();
+    let state = chip8.save_state();
+
+    // The state ends with the planes, the FX0A key, the audio pattern, the pitch and the 64x32 vram
+    let vram = state.len() - 64 * 32;
+    let key_wait = vram - 4 - 1 - 16 - 1;
+    let corrupt = |offset: usize, value: u8| {
+        let mut state = state.clone();
+        state[offset] = value;
+        state
+    };
+    for bad in [corrupt(key_wait, 0x10), corrupt(key_wait - 1, 0b100), corrupt(vram - 3, 128), corrupt(vram, 4)] {
+        assert_eq!(chip8.load_state(&bad), Err(StateError::Corrupted));
+    }
+    // No key held during FX0A is valid
+    chip8.load_state(&corrupt(key_wait, 0xFF)).unwrap();
+}
+
+#[test]
+fn jumps() {
+    assert_eq!(vip(0x1ABC, &[]).registers().pc, 0xABC);
+
+    // BNNN adds V0, or VX on the SUPER-CHIP
+    assert_eq!(vip(0xB300, &[(0, 4), (3, 8)]).registers().pc, 0x304);
+    assert_eq!(schip(0xB300, &[(0, 4), (3, 8)]).registers().pc, 0x308);
+}
+
+#[test]
+fn call_and_ret() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0x2206, 0x0000, 0x0000, 0x00EE]);
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().pc, 0x206);
+    assert_eq!(chip8.registers().sp, 1);
+    assert_eq!(chip8.stack()[0], 0x202);
+
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().pc, 0x202);
+    assert_eq!(chip8.registers().sp, 0);
+}
+
+#[test]
+fn stack_limits() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0x00EE]);
+    assert!(matches!(chip8.step(), Err(Chip8Error::StackUnderflow { pc: 0x200, opcode: 0x00EE })));
+
+    // Calling itself fills the stack after 16 calls
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0x2200]);
+    for _ in 0..16 {
+        chip8.step().unwrap();
+    }
+    assert_eq!(chip8.registers().sp, 16);
+    assert!(matches!(chip8.step(), Err(Chip8Error::StackOverflow { pc: 0x200, opcode: 0x2200 })));
+}
+
+#[test]
+fn index_register() {
+    assert_eq!(vip(0xA123, &[]).registers().i, 0x123);
+    assert_eq!(vip(0xF029, &[(0, 0xA)]).registers().i, 50);
+
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF01E]);
+    chip8.registers_mut().i = 0xFFFF;
+    chip8.registers_mut().v[0] = 2;
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().i, 0x0001);
+
+    // F000 NNNN loads a 16-bit address and skips over it
+    let mut chip8 = machine(Quirks::xo_chip(), &[0xF000, 0xBEEF]);
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().i, 0xBEEF);
+    assert_eq!(chip8.registers().pc, 0x204);
+}
+
+#[test]
+fn timers() {
+    let chip8 = vip(0xF015, &[(0, 30)]);
+    assert_eq!(chip8.registers().dt, 30);
+    let chip8 = vip(0xF018, &[(0, 40)]);
+    assert_eq!(chip8.registers().st, 40);
+
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF107]);
+    chip8.registers_mut().dt = 12;
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().v[1], 12);
+}
+
+#[test]
+fn rnd_applies_mask() {
+    for seed in 0..32 {
+        let mut chip8 = machine(Quirks::cosmac_vip(), &[0xC00F]);
+        chip8.seed_rng(seed);
+        chip8.step().unwrap();
+        assert_eq!(chip8.registers().v[0] & 0xF0, 0);
+    }
+    assert_eq!(vip(0xC000, &[]).registers().v[0], 0);
+}
+
+#[test]
+fn bcd() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF033]);
+    chip8.registers_mut().v[0] = 234;
+    chip8.registers_mut().i = 0x300;
+    chip8.step().unwrap();
+    assert_eq!(&chip8.memory()[0x300..0x303], &[2, 3, 4]);
+    assert_eq!(chip8.registers().i, 0x300);
+}
+
+#[test]
+fn store_and_load_registers() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF255, 0xF265]);
+    chip8.registers_mut().v[..3].copy_from_slice(&[1, 2, 3]);
+    chip8.registers_mut().i = 0x300;
+    chip8.step().unwrap();
+    assert_eq!(&chip8.memory()[0x300..0x304], &[1, 2, 3, 0]);
+    assert_eq!(chip8.registers().i, 0x303);
+
+    chip8.memory_mut()[0x303..0x306].copy_from_slice(&[4, 5, 6]);
+    chip8.step().unwrap();
+    assert_eq!(&chip8.registers().v[..3], &[4, 5, 6]);
+    assert_eq!(chip8.registers().i, 0x306);
+
+    // I is left alone on the SUPER-CHIP, and left on the last register on CHIP-48
+    let mut chip8 = machine(Quirks::schip(), &[0xF255]);
+    chip8.registers_mut().i = 0x300;
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().i, 0x300);
+
+    let mut chip8 = machine(Quirks::chip48(), &[0xF255, 0xF065]);
+    chip8.registers_mut().i = 0x300;
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().i, 0x302);
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().i, 0x302);
+}
+
+#[test]
+fn save_and_load_ranges() {
+    // 5XY2 stores VX to VY in order, which is reversed when X > Y
+    let mut chip8 = machine(Quirks::xo_chip(), &[0x5132, 0x5312]);
+    chip8.registers_mut().v[1..4].copy_from_slice(&[1, 2, 3]);
+    chip8.registers_mut().i = 0x300;
+    chip8.step().unwrap();
+    assert_eq!(&chip8.memory()[0x300..0x303], &[1, 2, 3]);
+    chip8.step().unwrap();
+    assert_eq!(&chip8.memory()[0x300..0x303], &[3, 2, 1]);
+    assert_eq!(chip8.registers().i, 0x300);
+}
+
+#[test]
+fn memory_bounds() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF155]);
+    chip8.registers_mut().i = 0xFFFF;
+    assert!(matches!(chip8.step(), Err(Chip8Error::MemoryOutOfBounds { addr: 0x10000, .. })));
+
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[]);
+    chip8.registers_mut().pc = 0xFFFF;
+    assert!(matches!(chip8.step(), Err(Chip8Error::PcOutOfBounds { pc: 0xFFFF })));
+}
+
+#[test]
+fn load_store_at_top_of_memory() {
+    // Storing or loading the last byte of memory increments I past it, wrapping around to 0
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xF055, 0xF065]);
+    chip8.registers_mut().v[0] = 0x42;
+    chip8.registers_mut().i = 0xFFFF;
+    chip8.step().unwrap();
+    assert_eq!(chip8.memory()[0xFFFF], 0x42);
+    assert_eq!(chip8.registers().i, 0);
+
+    chip8.registers_mut().v[0] = 0;
+    chip8.registers_mut().i = 0xFFFF;
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().v[0], 0x42);
+    assert_eq!(chip8.registers().i, 0);
+}
+
+#[test]
+fn rom_too_large() {
+    // The whole memory from 0x200 is available, one byte more is rejected
+    let rom = vec![0; 0x10000 - 0x200];
+    assert!(Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &rom).is_ok());
+    let rom = vec![0; 0x10000 - 0x200 + 1];
+    assert_eq!(
+        Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &rom).err(),
+        Some(Chip8Error::RomTooLarge { len: 0x10000 - 0x200 + 1, max: 0x10000 - 0x200 })
+    );
+}
+
+#[test]
+fn invalid_opcode() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0x8008]);
+    assert!(matches!(chip8.step(), Err(Chip8Error::InvalidOpcode { pc: 0x200, opcode: 0x8008 })));
+}
+
+#[test]
+fn draw_and_collide() {
+    // Draw the 0 glyph twice at (62, 0): it wraps to the left edge without clipping, and erases itself
+    let mut chip8 = machine(Quirks::xo_chip(), &[0xD015, 0xD015]);
+    chip8.registers_mut().v[0] = 62;
+    chip8.registers_mut().v[0xF] = 9;
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().v[0xF], 0);
+    assert_eq!(&chip8.vram()[0][62..], &[1, 1]);
+    assert_eq!(&chip8.vram()[0][..3], &[1, 1, 0]);
+    assert_eq!(&chip8.vram()[1][62..], &[1, 0]);
+    assert_eq!(&chip8.vram()[1][..3], &[0, 1, 0]);
+
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().v[0xF], 1);
+    assert!(chip8.vram().iter().all(|row| row.iter().all(|&pixel| pixel == 0)));
+}
+
+#[test]
+fn draw_clips_on_vip() {
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xD015]);
+    chip8.registers_mut().v[0] = 62;
+    chip8.step().unwrap();
+    assert_eq!(&chip8.vram()[0][62..], &[1, 1]);
+    assert_eq!(chip8.vram()[0][0], 0);
+}
+
+#[test]
+fn draw_wraps_starting_position() {
+    // The starting position wraps around even when sprites are clipped
+    let mut chip8 = machine(Quirks::cosmac_vip(), &[0xD011]);
+    chip8.registers_mut().v[0] = 64 + 8;
+    chip8.registers_mut().v[1] = 32 + 2;
+    chip8.step().unwrap();
+    assert_eq!(&chip8.vram()[2][8..12], &[1, 1, 1, 1]);
+}
+
+#[test]
+fn cls() {
+    let mut chip8 = machine(Quirks::schip(), &[0xD015, 0x00E0]);
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert!(chip8.vram().iter().all(|row| row.iter().all(|&pixel| pixel == 0)));
+}
+
+/// Coordinates of the pixels set in the given planes
+fn lit(chip8: &Chip8<HeadlessGraphics>, planes: u8) -> Vec<(usize, usize)> {
+    let mut pixels = Vec::new();
+    for (y, row) in chip8.vram().iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            if pixel & planes != 0 {
+                pixels.push((x, y));
+            }
+        }
+    }
+    pixels
+}
+
+#[test]
+fn hires_switching() {
+    let mut chip8 = machine(Quirks::schip(), &[0x00FF, 0xD011, 0x00FE]);
+    chip8.memory_mut()[0] = 0x80;
+    chip8.registers_mut().v[0] = 100;
+    chip8.registers_mut().v[1] = 50;
+
+    // Switching modes resizes the screen and clears it
+    chip8.step().unwrap();
+    assert!(chip8.is_hires());
+    assert_eq!((chip8.vram()[0].len(), chip8.vram().len()), (128, 64));
+    chip8.step().unwrap();
+    assert_eq!(lit(&chip8, 0b11), [(100, 50)]);
+
+    chip8.step().unwrap();
+    assert!(!chip8.is_hires());
+    assert_eq!((chip8.vram()[0].len(), chip8.vram().len()), (64, 32));
+    assert!(lit(&chip8, 0b11).is_empty());
+}
+
+#[test]
+fn scrolling() {
+    // Down by N rows, then right and left by 4 pixels, dropping what goes past the edge
+    let mut chip8 = machine(Quirks::schip(), &[0xD011, 0xD231, 0x00C2, 0x00FB, 0x00FC]);
+    chip8.memory_mut()[0] = 0x80;
+    chip8.registers_mut().v[0] = 10;
+    chip8.registers_mut().v[1] = 5;
+    chip8.registers_mut().v[2] = 62;
+    chip8.registers_mut().v[3] = 30;
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert_eq!(lit(&chip8, 0b11), [(10, 5), (62, 30)]);
+
+    chip8.step().unwrap();
+    assert_eq!(lit(&chip8, 0b11), [(10, 7)]);
+    chip8.step().unwrap();
+    assert_eq!(lit(&chip8, 0b11), [(14, 7)]);
+    chip8.step().unwrap();
+    assert_eq!(lit(&chip8, 0b11), [(10, 7)]);
+}
+
+#[test]
+fn draw_large_sprite() {
+    // DXY0 draws 16x16 pixels from 32 bytes, two per row
+    let mut chip8 = machine(Quirks::schip(), &[0x00FF, 0xD120, 0xD120]);
+    chip8.memory_mut()[0x300..0x320].fill(0xFF);
+    chip8.registers_mut().i = 0x300;
+    chip8.registers_mut().v[1] = 8;
+    chip8.registers_mut().v[2] = 4;
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().v[0xF], 0);
+    let pixels = lit(&chip8, 0b11);
+    assert_eq!(pixels.len(), 16 * 16);
+    assert_eq!((pixels[0], pixels[255]), ((8, 4), (23, 19)));
+
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().v[0xF], 1);
+    assert!(lit(&chip8, 0b11).is_empty());
+}
+
+#[test]
+fn large_font() {
+    // 10 byte digits follow the 80 bytes of the small font
+    let chip8 = schip(0xF330, &[(3, 7)]);
+    assert_eq!(chip8.registers().i, 80 + 7 * 10);
+}
+
+#[test]
+fn exit() {
+    let mut chip8 = machine(Quirks::schip(), &[0x00FD, 0x6001]);
+    chip8.step().unwrap();
+    assert!(chip8.is_halted());
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().v[0], 0);
+}
+
+#[test]
+fn rpl_flags() {
+    let mut chip8 = machine(Quirks::schip(), &[0xF275, 0x6000, 0xF185]);
+    chip8.registers_mut().v[..4].copy_from_slice(&[1, 2, 3, 4]);
+    chip8.step().unwrap();
+    assert_eq!(&chip8.rpl_flags()[..4], &[1, 2, 3, 0]);
+
+    // Only V0 to VX are restored
+    chip8.registers_mut().v[..3].copy_from_slice(&[0, 0, 0]);
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert_eq!(&chip8.registers().v[..4], &[1, 2, 0, 4]);
+}
+
+#[test]
+fn draw_on_planes() {
+    // With both planes selected the second sprite follows the first in memory
+    let mut chip8 = machine(Quirks::xo_chip(), &[0xF301, 0xD011, 0xF101, 0xD011]);
+    chip8.memory_mut()[0x300..0x302].copy_from_slice(&[0xC0, 0x60]);
+    chip8.registers_mut().i = 0x300;
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert_eq!(&chip8.vram()[0][..4], &[1, 3, 2, 0]);
+
+    // Collisions are reported for the selected planes only
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().v[0xF], 1);
+    assert_eq!(&chip8.vram()[0][..4], &[0, 2, 2, 0]);
+}
+
+#[test]
+fn clear_and_scroll_selected_planes() {
+    let mut chip8 = machine(Quirks::xo_chip(), &[0xF301, 0xD011, 0xF101, 0x00C1, 0x00D1, 0xF201, 0x00E0]);
+    chip8.memory_mut()[0x300..0x302].copy_from_slice(&[0x80, 0x80]);
+    chip8.registers_mut().i = 0x300;
+    chip8.registers_mut().v[1] = 4;
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert_eq!(lit(&chip8, 0b11), [(0, 4)]);
+
+    // Scrolling down then up only moves the first plane
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert_eq!((lit(&chip8, 0b01), lit(&chip8, 0b10)), (vec![(0, 5)], vec![(0, 4)]));
+    chip8.step().unwrap();
+    assert_eq!((lit(&chip8, 0b01), lit(&chip8, 0b10)), (vec![(0, 4)], vec![(0, 4)]));
+
+    // Clearing the second plane leaves the first
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert_eq!((lit(&chip8, 0b01), lit(&chip8, 0b10)), (vec![(0, 4)], vec![]));
+}
+
+#[test]
+fn long_index_load() {
+    // F000 NNNN is 4 bytes long, loading an address past 0xFFF
+    let mut chip8 = machine(Quirks::xo_chip(), &[0xF000, 0xABCD, 0x6001]);
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().i, 0xABCD);
+    assert_eq!(chip8.registers().pc, 0x204);
+    chip8.step().unwrap();
+    assert_eq!(chip8.registers().v[0], 1);
+}
+
+#[test]
+fn audio_pattern_and_pitch() {
+    let mut chip8 = machine(Quirks::xo_chip(), &[0xF002, 0xF13A]);
+    let pattern: Vec<u8> = (0..16).collect();
+    chip8.memory_mut()[0x300..0x310].copy_from_slice(&pattern);
+    chip8.registers_mut().i = 0x300;
+    chip8.registers_mut().v[1] = 80;
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    assert_eq!(chip8.graphics().audio_pattern().0[..], pattern[..]);
+    assert_eq!(chip8.graphics().audio_pattern().1, 80);
+}
+
+#[test]
+fn vip_hires_entry_point() {
+    // Jumping to 0x260 from 0x200 skips the bundled interpreter, the program starts at 0x2C0 on a 64x64 screen
+    let mut program = vec![0x1260];
+    program.resize((0x2C0 - 0x200) / 2, 0);
+    program.extend_from_slice(&[0xD011, 0xD231]);
+    let mut chip8 = machine(Quirks::cosmac_vip(), &program);
+    chip8.memory_mut()[0] = 0x80;
+    chip8.registers_mut().v[1] = 40;
+    chip8.registers_mut().v[2] = 63;
+    chip8.registers_mut().v[3] = 63;
+    chip8.step().unwrap();
+    assert!(chip8.is_vip_hires());
+    assert_eq!(chip8.registers().pc, 0x2C0);
+    assert_eq!((chip8.vram()[0].len(), chip8.vram().len()), (64, 64));
+
+    chip8.step().unwrap();
+    chip8.tick_timers();
+    chip8.step().unwrap();
+    assert_eq!(lit(&chip8, 0b11), [(0, 40), (63, 63)]);
+}
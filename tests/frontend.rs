@@ -0,0 +1,46 @@
+use chip8::graphics::HeadlessGraphics;
+use chip8::{Chip8, Command, Frontend, Keypad, Quirks};
+
+/// Keypad pressing a key for a few frames, standing in for input coming from elsewhere
+struct TappedKey {
+    key: u8,
+    frames: u32,
+}
+
+impl Keypad for TappedKey {
+    fn update(&mut self, _timeout_millis: u32) {
+        self.frames = self.frames.saturating_sub(1);
+    }
+
+    fn is_key_pressed(&self, key: u8) -> bool {
+        key == self.key && self.frames > 0
+    }
+
+    fn should_close(&self) -> bool {
+        false
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        None
+    }
+}
+
+#[test]
+fn mixed_components() {
+    // Wait for a key, show its glyph and beep, with the input taken from a separate keypad
+    let rom = [0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x62, 0x10, 0xF2, 0x18];
+    let frontend = Frontend {
+        display: HeadlessGraphics::new(64, 32),
+        keypad: TappedKey { key: 0x1, frames: 2 },
+        beeper: HeadlessGraphics::new(64, 32),
+    };
+    let mut chip8 = Chip8::with_rom(0, frontend, Quirks::cosmac_vip(), &rom).unwrap();
+    for _ in 0..3 {
+        chip8.run_frame(5).unwrap();
+    }
+
+    let frontend = chip8.graphics();
+    assert_eq!(&frontend.display.framebuffer()[0][..4], &[0, 0, 1, 0]);
+    assert!(frontend.beeper.is_sound_on());
+    assert!(!frontend.display.is_sound_on());
+}
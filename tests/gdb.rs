@@ -0,0 +1,207 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+use chip8::graphics::HeadlessGraphics;
+use chip8::{gdb, Chip8, Quirks};
+
+/// GDB side of the remote serial protocol
+struct Client {
+    stream: TcpStream,
+    ack: bool,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Client {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        Client { stream, ack: true }
+    }
+
+    fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    /// Send a packet and return the reply, checking that both are acknowledged while acks are on
+    fn request(&mut self, data: &str) -> String {
+        self.send_raw(framed(data).as_bytes());
+        if self.ack {
+            assert_eq!(self.read_byte() as char, '+', "{} was not acknowledged", data);
+        }
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.read_byte() as char, '$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = String::from_utf8(vec![self.read_byte(), self.read_byte()]).unwrap();
+        assert_eq!(u8::from_str_radix(&checksum, 16).unwrap(), checksum_of(&data));
+        if self.ack {
+            self.send_raw(b"+");
+        }
+        String::from_utf8(data).unwrap()
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn framed(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+}
+
+/// Serve `chip8` to a client running `session` in another thread, until the client detaches or hangs up
+fn with_gdb(chip8: &mut Chip8<HeadlessGraphics>, session: impl FnOnce(&mut Client) + Send + 'static) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || session(&mut Client::connect(addr)));
+    gdb::serve_on(chip8, &listener).unwrap();
+    if let Err(panic) = client.join() {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+#[test]
+fn packet_framing() {
+    let mut chip8 = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &[0x12, 0x00]).unwrap();
+    with_gdb(&mut chip8, |client| {
+        assert_eq!(client.request("?"), "S05");
+
+        // A corrupted packet is rejected so GDB sends it again
+        client.send_raw(b"$?#00");
+        assert_eq!(client.read_byte() as char, '-');
+        assert_eq!(client.request("?"), "S05");
+
+        // Acknowledgments and interrupts between packets are skipped
+        client.send_raw(b"+\x03");
+        assert_eq!(client.request("?"), "S05");
+
+        // Unsupported packets get an empty reply
+        assert_eq!(client.request("vMustReplyEmpty"), "");
+
+        // The packet turning acks off is acknowledged, none after it are
+        assert_eq!(client.request("QStartNoAckMode"), "OK");
+        client.ack = false;
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request("D"), "OK");
+    });
+}
+
+#[test]
+fn target_description() {
+    let mut chip8 = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &[0x12, 0x00]).unwrap();
+    with_gdb(&mut chip8, |client| {
+        assert!(client.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+
+        // The description is read in chunks, the last one starting with l
+        let mut xml = String::new();
+        loop {
+            let chunk = client.request(&format!("qXfer:features:read:target.xml:{:x},40", xml.len()));
+            let (kind, data) = chunk.split_at(1);
+            xml.push_str(data);
+            if kind == "l" {
+                break;
+            }
+            assert_eq!(kind, "m");
+        }
+        assert!(xml.starts_with("<?xml"));
+        assert_eq!(xml.matches("<reg ").count(), 21);
+        client.request("D");
+    });
+}
+
+#[test]
+fn read_and_write_registers() {
+    let mut chip8 = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &[0x12, 0x00]).unwrap();
+    let reg = chip8.registers_mut();
+    reg.v[0x0] = 0x12;
+    reg.v[0xF] = 0xFF;
+    reg.i = 0x345;
+    reg.dt = 0x20;
+
+    with_gdb(&mut chip8, |client| {
+        // V0-VF, then I and PC in big endian, then SP, DT and ST
+        let expected = format!("12{}ff03450200002000", "00".repeat(14));
+        assert_eq!(client.request("g"), expected);
+        assert_eq!(client.request("p11"), "0200");
+        assert_eq!(client.request("p15"), "E01");
+
+        assert_eq!(client.request("P3=7f"), "OK");
+        assert_eq!(client.request("P10=0abc"), "OK");
+        assert_eq!(client.request("p3"), "7f");
+        assert_eq!(client.request("p10"), "0abc");
+
+        let written = format!("{}01230300010203", "01".repeat(16));
+        assert_eq!(client.request(&format!("G{}", written)), "OK");
+        assert_eq!(client.request("g"), written);
+        // A short write is refused
+        assert_eq!(client.request("G0102"), "E01");
+        assert_eq!(client.request("D"), "OK");
+    });
+
+    let reg = chip8.registers();
+    assert_eq!(reg.v, [0x01; 16]);
+    assert_eq!((reg.i, reg.pc, reg.sp, reg.dt, reg.st), (0x123, 0x300, 0x01, 0x02, 0x03));
+}
+
+#[test]
+fn read_and_write_memory() {
+    let rom = [
+        0x60, 0x12, // LD V0, 0x12
+        0x12, 0x02, // JP 0x202
+    ];
+    let mut chip8 = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &rom).unwrap();
+    with_gdb(&mut chip8, |client| {
+        assert_eq!(client.request("m200,4"), "60121202");
+        assert_eq!(client.request("M300,3:a1b2c3"), "OK");
+        assert_eq!(client.request("m300,3"), "a1b2c3");
+
+        // Writes must match their length and both stay within memory
+        assert_eq!(client.request("M300,2:a1"), "E01");
+        assert_eq!(client.request("M300,1:zz"), "E01");
+        assert_eq!(client.request("mffff,2"), "E01");
+        assert_eq!(client.request("Mffff,2:0102"), "E01");
+        assert_eq!(client.request("D"), "OK");
+    });
+
+    assert_eq!(chip8.memory()[0x300..0x304], [0xA1, 0xB2, 0xC3, 0x00]);
+}
+
+#[test]
+fn step_and_breakpoint() {
+    let rom = [
+        0x60, 0x12, // LD V0, 0x12
+        0x70, 0x01, // ADD V0, 1
+        0x70, 0x01, // ADD V0, 1
+        0x12, 0x06, // JP 0x206
+    ];
+    let mut chip8 = Chip8::with_rom(600, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), &rom).unwrap();
+    with_gdb(&mut chip8, |client| {
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p11"), "0202");
+        assert_eq!(client.request("p0"), "12");
+
+        assert_eq!(client.request("Z0,206,2"), "OK");
+        assert_eq!(client.request("c"), "T05swbreak:;");
+        assert_eq!(client.request("p11"), "0206");
+        assert_eq!(client.request("p0"), "14");
+
+        // Watchpoints are not supported
+        assert_eq!(client.request("Z2,300,1"), "");
+        assert_eq!(client.request("z0,206,2"), "OK");
+        assert_eq!(client.request("D"), "OK");
+    });
+}
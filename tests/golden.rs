@@ -0,0 +1,162 @@
+//! Runs test ROMs headlessly and compares the final screen against the reference images in `tests/golden`.
+//!
+//! References are plain PGM images, with one gray level per bitplane combination, so they open in image
+//! viewers and changes show up in `git diff`. After an intended change in behaviour, regenerate them with
+//! `CHIP8_BLESS=1 cargo test --test golden` and review the result.
+//!
+//! The community test suite ROMs (corax+, flags and quirks from Timendus' chip8-test-suite) are not
+//! distributed with the repo, so their tests are ignored by default. Copy the ROMs into `tests/roms`, bless
+//! their references once and run them with `cargo test --test golden -- --ignored`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chip8::graphics::HeadlessGraphics;
+use chip8::{Chip8, Quirks};
+
+/// Instructions executed per frame, matching the default clock of 500Hz
+const CYCLES_PER_FRAME: usize = 8;
+
+struct Run {
+    rom: PathBuf,
+    frames: usize,
+    /// Key presses and releases as (frame, key, pressed)
+    keys: Vec<(u64, u8, bool)>,
+}
+
+impl Run {
+    fn new(rom: impl AsRef<Path>, frames: usize) -> Run {
+        Run {
+            rom: rom.as_ref().to_path_buf(),
+            frames,
+            keys: Vec::new(),
+        }
+    }
+
+    /// Hold `key` down for a few frames starting at `frame`
+    fn tap(mut self, frame: u64, key: u8) -> Run {
+        self.keys.push((frame, key, true));
+        self.keys.push((frame + 3, key, false));
+        self
+    }
+
+    /// Run the ROM and compare the final screen against `tests/golden/<name>.pgm`
+    fn check(self, name: &str) {
+        let rom = fs::read(&self.rom).unwrap_or_else(|err| panic!("Failed to read {}: {}", self.rom.display(), err));
+
+        let mut gfx = HeadlessGraphics::new(64, 32);
+        for (frame, key, pressed) in self.keys {
+            gfx.script_key(frame, key, pressed);
+        }
+        let mut chip8 = Chip8::with_rom(0, gfx, Quirks::cosmac_vip(), &rom).unwrap();
+        chip8.seed_rng(0xC8);
+        for _ in 0..self.frames {
+            chip8.run_frame(CYCLES_PER_FRAME).unwrap();
+        }
+
+        let image = to_pgm(chip8.graphics().framebuffer());
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.pgm", name));
+        if env::var_os("CHIP8_BLESS").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, image).unwrap();
+            return;
+        }
+
+        let reference = fs::read_to_string(&path).unwrap_or_else(|err| {
+            panic!("Failed to read {}: {}, run with CHIP8_BLESS=1 to create it", path.display(), err)
+        });
+        if image != reference {
+            panic!(
+                "Screen of {} differs from {}\n\nExpected:\n{}\nGot:\n{}",
+                self.rom.display(),
+                path.display(),
+                to_ascii(&reference),
+                to_ascii(&image),
+            );
+        }
+    }
+}
+
+fn bundled_rom(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("roms/programs").join(name)
+}
+
+/// Path of a community test ROM, which has to be copied into `tests/roms`
+fn community_rom(name: &str) -> PathBuf {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms").join(name);
+    assert!(path.exists(), "{} is missing, copy it from Timendus' chip8-test-suite", path.display());
+    path
+}
+
+fn to_pgm(vram: &[Vec<u8>]) -> String {
+    let mut image = format!("P2\n{} {}\n3\n", vram[0].len(), vram.len());
+    for row in vram {
+        let pixels: Vec<String> = row.iter().map(|pixel| pixel.to_string()).collect();
+        image.push_str(&pixels.join(" "));
+        image.push('\n');
+    }
+    image
+}
+
+/// Render a PGM image as text, to show mismatches in the test output
+fn to_ascii(image: &str) -> String {
+    image
+        .lines()
+        .skip(3)
+        .map(|row| row.split(' ').map(|pixel| if pixel == "0" { '.' } else { '#' }).collect::<String>() + "\n")
+        .collect()
+}
+
+#[test]
+fn ibm_logo() {
+    Run::new(bundled_rom("IBM Logo.ch8"), 60).check("ibm_logo");
+}
+
+#[test]
+fn delay_timer_test() {
+    // Lower V3 from 0 to 255 with 8, then start the countdown with 5 and stop halfway through. Every DRW
+    // waits for the next frame, so the last digit is never on screen at the end of a frame
+    Run::new(bundled_rom("Delay Timer Test [Matthew Mikolay, 2010].ch8"), 129)
+        .tap(10, 0x8)
+        .tap(20, 0x5)
+        .check("delay_timer_test");
+}
+
+#[test]
+fn division_test() {
+    Run::new(bundled_rom("Division Test [Sergey Naydenov, 2010].ch8"), 300).check("division_test");
+}
+
+#[test]
+fn sqrt_test() {
+    Run::new(bundled_rom("SQRT Test [Sergey Naydenov, 2010].ch8"), 300).check("sqrt_test");
+}
+
+#[test]
+fn random_number_test() {
+    // Every key press shows a new number, drawn from the seeded generator
+    Run::new(bundled_rom("Random Number Test [Matthew Mikolay, 2010].ch8"), 60)
+        .tap(10, 0x1)
+        .tap(30, 0x1)
+        .check("random_number_test");
+}
+
+#[test]
+#[ignore = "needs community ROMs"]
+fn corax_plus() {
+    Run::new(community_rom("3-corax+.ch8"), 120).check("corax_plus");
+}
+
+#[test]
+#[ignore = "needs community ROMs"]
+fn flags() {
+    Run::new(community_rom("4-flags.ch8"), 120).check("flags");
+}
+
+#[test]
+#[ignore = "needs community ROMs"]
+fn quirks_vip() {
+    // The ROM starts with a menu, 1 selects the CHIP-8 platform
+    Run::new(community_rom("5-quirks.ch8"), 300).tap(10, 0x1).check("quirks_vip");
+}
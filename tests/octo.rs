@@ -0,0 +1,73 @@
+use chip8::octo::compile;
+
+#[test]
+fn instructions_and_main() {
+    let source = "
+        :alias x v3
+        :const SPEED 2
+        : main
+            clear
+            x := SPEED
+            x += 1
+            i := box
+            sprite x x 3
+            loop again
+        : box
+            0b11110000 0x90 0xF0
+    ";
+    let rom = compile(source).unwrap();
+    assert_eq!(rom, [
+        0x12, 0x02, 0x00, 0xE0, 0x63, 0x02, 0x73, 0x01, 0xA2, 0x0E, 0xD3, 0x33, 0x12, 0x0C, 0xF0, 0x90, 0xF0,
+    ]);
+}
+
+#[test]
+fn control_flow() {
+    let source = "
+        loop
+            if v0 == 5 then v1 := 1
+            while v2 != v3
+            if v4 key begin
+                v5 := 1
+            else
+                v5 := 2
+            end
+        again
+    ";
+    let rom = compile(source).unwrap();
+    assert_eq!(rom, [
+        0x40, 0x05, 0x61, 0x01, // if v0 == 5 then v1 := 1
+        0x92, 0x30, 0x12, 0x14, // while v2 != v3
+        0xE4, 0x9E, 0x12, 0x10, 0x65, 0x01, 0x12, 0x12, 0x65, 0x02, // if ... begin ... else ... end
+        0x12, 0x00, // again
+    ]);
+}
+
+#[test]
+fn macros_calc_and_org() {
+    let source = "
+        :macro twice reg { reg += 1 reg += 1 }
+        :calc addr { 0x300 + 2 * 8 }
+        twice v1
+        jump later
+        :org addr
+        : later
+        :byte { HERE >> 4 }
+    ";
+    let rom = compile(source).unwrap();
+    assert_eq!(&rom[..6], [0x71, 0x01, 0x71, 0x01, 0x13, 0x10]);
+    assert_eq!(rom.len(), 0x311 - 0x200);
+    assert_eq!(rom[0x110], 0x31);
+}
+
+#[test]
+fn errors_report_line_numbers() {
+    let err = compile("clear\nv0 := 300\n").unwrap_err();
+    assert_eq!(err.line, 2);
+
+    let err = compile("clear\n\njump nowhere\n").unwrap_err();
+    assert_eq!(err.line, 3);
+
+    let err = compile("loop\nclear\n").unwrap_err();
+    assert_eq!(err.line, 1);
+}
@@ -0,0 +1,169 @@
+use std::time::{Duration, Instant};
+
+use std::collections::VecDeque;
+
+use chip8::graphics::HeadlessGraphics;
+use chip8::{Chip8, Command, Frontend, Keypad, FRAME_RATE};
+
+/// Count loop iterations until the delay timer, started at 90, runs out
+const ROM: [u8; 14] = [
+    0x6F, 0x5A, // LD VF, 90
+    0xFF, 0x15, // LD DT, VF
+    0x71, 0x01, // ADD V1, 1
+    0xF0, 0x07, // LD V0, DT
+    0x30, 0x00, // SE V0, 0
+    0x12, 0x04, // JP 0x204
+    0x00, 0xFD, // EXIT
+];
+
+/// Count frames, running two instructions per frame at 120Hz
+const COUNTER_ROM: [u8; 4] = [
+    0x71, 0x01, // ADD V1, 1
+    0x12, 0x00, // JP 0x200
+];
+
+/// Keypad issuing commands at given frames, and closing after the last one
+struct Script {
+    frame: u64,
+    commands: VecDeque<(u64, Command)>,
+    pending: VecDeque<Command>,
+    close_at: u64,
+}
+
+impl Script {
+    fn new(commands: &[(u64, Command)], close_at: u64) -> Script {
+        Script {
+            frame: 0,
+            commands: commands.iter().copied().collect(),
+            pending: VecDeque::new(),
+            close_at,
+        }
+    }
+}
+
+impl Keypad for Script {
+    fn update(&mut self, _timeout_millis: u32) {
+        while self.commands.front().is_some_and(|&(frame, _)| frame <= self.frame) {
+            self.pending.push_back(self.commands.pop_front().unwrap().1);
+        }
+        self.frame += 1;
+    }
+
+    fn is_key_pressed(&self, _key: u8) -> bool {
+        false
+    }
+
+    fn should_close(&self) -> bool {
+        self.frame >= self.close_at
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        self.pending.pop_front()
+    }
+}
+
+/// Run the counter ROM with the given commands, returning the frames run, how long it took and the last
+/// status shown
+fn run_script(commands: &[(u64, Command)], close_at: u64) -> (u8, Duration, String) {
+    let frontend = Frontend {
+        display: HeadlessGraphics::new(64, 32),
+        keypad: Script::new(commands, close_at),
+        beeper: HeadlessGraphics::new(64, 32),
+    };
+    let mut chip8 = Chip8::with_rom(120, frontend, Default::default(), &COUNTER_ROM).unwrap();
+    chip8.set_turbo_speed(4.0);
+    let start = Instant::now();
+    chip8.run().unwrap();
+    (chip8.registers().v[1], start.elapsed(), chip8.graphics().display.status().to_string())
+}
+
+#[test]
+fn run_paces_frames() {
+    // 270Hz is 4.5 instructions per frame, which alternates between frames of 4 and 5 instructions
+    let mut reference = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Default::default(), &ROM).unwrap();
+    let mut frame = 0;
+    while !reference.is_halted() {
+        reference.run_frame(4 + frame % 2).unwrap();
+        frame += 1;
+    }
+
+    let mut chip8 = Chip8::with_rom(270, HeadlessGraphics::new(64, 32), Default::default(), &ROM).unwrap();
+    let start = Instant::now();
+    chip8.run().unwrap();
+    let elapsed = start.elapsed();
+
+    assert_eq!(chip8.registers().v, reference.registers().v);
+    assert_eq!(chip8.graphics().ticks(), frame as u64);
+    assert!(
+        elapsed >= Duration::from_secs(frame as u64 - 1) / FRAME_RATE,
+        "{} frames took {:?}",
+        frame,
+        elapsed
+    );
+
+    // Statistics are refreshed every second, and the run lasted a second and a half
+    let stats = chip8.frame_stats();
+    assert!(stats.fps > 0.0 && stats.ips > stats.fps);
+    assert!(chip8.graphics().status().contains('%'));
+}
+
+#[test]
+fn unlimited_speed_keeps_timers_at_60hz() {
+    // With no frequency the loop runs as many instructions as fit in each frame, the timers still tick
+    // once per frame
+    let mut chip8 = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Default::default(), &ROM).unwrap();
+    chip8.run().unwrap();
+
+    assert!(chip8.is_halted());
+    assert_eq!(chip8.graphics().ticks(), 91);
+    assert!(chip8.frame_stats().ips > 100.0 * FRAME_RATE as f64);
+}
+
+#[test]
+fn pause_and_frame_advance() {
+    // Paused from the start, two frames are advanced one by one
+    let (frames, _, status) = run_script(
+        &[(0, Command::Pause), (5, Command::FrameAdvance), (10, Command::FrameAdvance)],
+        20,
+    );
+    assert_eq!(frames, 2);
+    assert_eq!(status, "Paused - 120 Hz");
+
+    // Frame advance only works while paused, and resuming runs every frame again
+    let (frames, _, status) = run_script(
+        &[(0, Command::FrameAdvance), (0, Command::Pause), (5, Command::FrameAdvance), (10, Command::Pause)],
+        20,
+    );
+    assert_eq!(frames, 1 + 10);
+    assert_eq!(status, "120 Hz");
+}
+
+#[test]
+fn turbo_and_slow_motion() {
+    // Frames are still run one per update, only faster or slower
+    let (frames, elapsed, status) = run_script(&[(0, Command::Turbo(true))], 40);
+    assert_eq!(frames, 40);
+    assert!(elapsed < Duration::from_secs(40) / FRAME_RATE, "40 turbo frames took {:?}", elapsed);
+    assert_eq!(status, "Turbo - 120 Hz");
+
+    let (frames, elapsed, status) = run_script(
+        &[(0, Command::SlowMotion), (4, Command::Turbo(true)), (6, Command::Turbo(false))],
+        8,
+    );
+    assert_eq!(frames, 8);
+    assert!(elapsed >= Duration::from_secs(6) * 4 / FRAME_RATE, "8 frames, 6 of them in slow motion, took {:?}", elapsed);
+    assert_eq!(status, "Slow motion - 120 Hz");
+}
+
+#[test]
+fn speed_hotkeys() {
+    // Every press runs one instruction per frame more or less, down to one
+    let (adds, _, status) =
+        run_script(&[(0, Command::IncreaseSpeed), (1, Command::IncreaseSpeed), (2, Command::DecreaseSpeed)], 4);
+    // 3 + 4 + 3 + 3 instructions, every other one an ADD starting with the first
+    assert_eq!(adds, 7);
+    assert_eq!(status, "180 Hz");
+
+    let (_, _, status) = run_script(&[(0, Command::DecreaseSpeed), (1, Command::DecreaseSpeed)], 3);
+    assert_eq!(status, "60 Hz");
+}
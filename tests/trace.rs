@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use chip8::graphics::HeadlessGraphics;
+use chip8::trace::Tracer;
+use chip8::{Chip8, Quirks};
+
+const ROM: [u8; 16] = [
+    0x60, 0xFF, // LD V0, 0xFF
+    0x61, 0x02, // LD V1, 0x02
+    0x80, 0x14, // ADD V0, V1
+    0xA3, 0x00, // LD I, 0x300
+    0x22, 0x0C, // CALL 0x20C
+    0x12, 0x0A, // JP 0x20A
+    0xF1, 0x55, // LD [I], V1
+    0x00, 0xEE, // RET
+];
+
+/// Sink for the trace, still readable once the tracer owns it
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Output {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.borrow().clone()).unwrap().lines().map(str::to_string).collect()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `steps` instructions of `rom` with a tracer set up by `setup`, returning the lines written
+fn trace(rom: &[u8], steps: usize, setup: impl FnOnce(&mut Tracer)) -> Vec<String> {
+    let output = Output::default();
+    let mut tracer = Tracer::new(Box::new(output.clone()));
+    setup(&mut tracer);
+    let mut chip8 = Chip8::with_rom(0, HeadlessGraphics::new(64, 32), Quirks::cosmac_vip(), rom).unwrap();
+    chip8.set_tracer(tracer);
+    for _ in 0..steps {
+        let _ = chip8.step();
+    }
+    output.lines()
+}
+
+#[test]
+fn lines_list_the_changes() {
+    // Registers in a fixed order, then the stack and the memory
+    assert_eq!(trace(&ROM, 8, |_| ()), [
+        "00000000 0x200 0x60FF LD V0, 0xFF V0=FF",
+        "00000001 0x202 0x6102 LD V1, 0x02 V1=02",
+        "00000002 0x204 0x8014 ADD V0, V1 V0=01 VF=01",
+        "00000003 0x206 0xA300 LD I, 0x300 I=300",
+        "00000004 0x208 0x220C CALL 0x20C SP=01 S0=20A",
+        "00000005 0x20C 0xF155 LD [I], V1 I=302 [0x300]=01 [0x301]=02",
+        "00000006 0x20E 0x00EE RET SP=00",
+        "00000007 0x20A 0x120A JP 0x20A",
+    ]);
+}
+
+#[test]
+fn filters() {
+    // Cycles keep counting the instructions that are left out
+    assert_eq!(trace(&ROM, 8, |tracer| tracer.set_addrs(0x20C..=0x20E)), [
+        "00000005 0x20C 0xF155 LD [I], V1 I=302 [0x300]=01 [0x301]=02",
+        "00000006 0x20E 0x00EE RET SP=00",
+    ]);
+    assert_eq!(trace(&ROM, 8, |tracer| tracer.set_mnemonics(&["ld".to_string(), "RET".to_string()])), [
+        "00000000 0x200 0x60FF LD V0, 0xFF V0=FF",
+        "00000001 0x202 0x6102 LD V1, 0x02 V1=02",
+        "00000003 0x206 0xA300 LD I, 0x300 I=300",
+        "00000005 0x20C 0xF155 LD [I], V1 I=302 [0x300]=01 [0x301]=02",
+        "00000006 0x20E 0x00EE RET SP=00",
+    ]);
+}
+
+#[test]
+fn last_instructions_are_written_on_fault() {
+    let rom = [
+        0x60, 0x01, // LD V0, 0x01
+        0x61, 0x02, // LD V1, 0x02
+        0x62, 0x03, // LD V2, 0x03
+        0x00, 0xEE, // RET, with an empty stack
+    ];
+    assert!(trace(&rom, 3, |tracer| tracer.set_last(2)).is_empty());
+    assert_eq!(trace(&rom, 4, |tracer| tracer.set_last(2)), [
+        "00000001 0x202 0x6102 LD V1, 0x02 V1=02",
+        "00000002 0x204 0x6203 LD V2, 0x03 V2=03",
+        "00000003 0x206 0x00EE fault: stack underflow executing 00EE at 206",
+    ]);
+}